@@ -4,16 +4,18 @@ use local_logger::schema::LogEntry;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tempfile::TempDir;
 
 /// Create a test LogWriter-compatible temporary directory
+#[allow(dead_code)]
 pub fn create_bench_dir() -> TempDir {
     TempDir::new().expect("Failed to create temp dir")
 }
 
 /// Create a log file with a specified number of entries
-pub fn create_log_file_with_entries(dir: &PathBuf, num_entries: usize) -> PathBuf {
+#[allow(dead_code)]
+pub fn create_log_file_with_entries(dir: &Path, num_entries: usize) -> PathBuf {
     let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
     let log_path = dir.join(format!("{}.jsonl", date));
 
@@ -119,7 +121,8 @@ pub fn create_complex_entry() -> LogEntry {
 }
 
 /// Create a large log file for memory benchmarks
-pub fn create_large_log_file(dir: &PathBuf, size_mb: usize) -> PathBuf {
+#[allow(dead_code)]
+pub fn create_large_log_file(dir: &Path, size_mb: usize) -> PathBuf {
     let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
     let log_path = dir.join(format!("{}.jsonl", date));
 
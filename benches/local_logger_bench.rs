@@ -5,14 +5,14 @@ use local_logger::log_writer::LogWriter;
 use local_logger::schema::LogEntry;
 use std::fs::{File, OpenOptions};
 use std::io::{BufWriter, Write, BufReader, BufRead, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Barrier};
 use std::thread;
 use tempfile::TempDir;
 use uuid::Uuid;
 
 /// Create a temporary log file with n entries for benchmarking
-fn create_test_log_file(dir: &PathBuf, entries: usize) -> PathBuf {
+fn create_test_log_file(dir: &Path, entries: usize) -> PathBuf {
     let file_path = dir.join(format!("{}.jsonl", chrono::Utc::now().format("%Y-%m-%d")));
     let mut file = BufWriter::new(File::create(&file_path).unwrap());
 
@@ -113,7 +113,7 @@ fn bench_read_entire_file(c: &mut Criterion) {
     let mut group = c.benchmark_group("read_entire_file");
 
     for size in [100, 1000, 10000].iter() {
-        let log_file = create_test_log_file(&temp_dir.path().to_path_buf(), *size);
+        let log_file = create_test_log_file(temp_dir.path(), *size);
 
         group.throughput(Throughput::Elements(*size as u64));
         group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, _| {
@@ -123,11 +123,9 @@ fn bench_read_entire_file(c: &mut Criterion) {
                 let mut entries = Vec::new();
 
                 // Old approach: read entire file
-                for line in reader.lines() {
-                    if let Ok(line) = line {
-                        if let Ok(entry) = serde_json::from_str::<LogEntry>(&line) {
-                            entries.push(entry);
-                        }
+                for line in reader.lines().map_while(Result::ok) {
+                    if let Ok(entry) = serde_json::from_str::<LogEntry>(&line) {
+                        entries.push(entry);
                     }
                 }
 
@@ -148,7 +146,7 @@ fn bench_tail_reading(c: &mut Criterion) {
     group.sample_size(20); // Reduce sample size for large file tests
 
     for size in [100, 1000, 10000, 100000].iter() {
-        let log_file = create_test_log_file(&temp_dir.path().to_path_buf(), *size);
+        let log_file = create_test_log_file(temp_dir.path(), *size);
 
         group.throughput(Throughput::Elements(50)); // We're reading 50 entries
         group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, _| {
@@ -244,7 +242,7 @@ fn bench_hook_mode_full(c: &mut Criterion) {
 /// Benchmark memory usage by comparing approaches
 fn bench_memory_usage(c: &mut Criterion) {
     let temp_dir = TempDir::new().unwrap();
-    let large_file = create_test_log_file(&temp_dir.path().to_path_buf(), 100000);
+    let large_file = create_test_log_file(temp_dir.path(), 100000);
 
     let mut group = c.benchmark_group("memory_usage");
     group.sample_size(10); // Reduce sample size for large operations
@@ -257,11 +255,9 @@ fn bench_memory_usage(c: &mut Criterion) {
             let reader = BufReader::new(file);
             let mut count = 0;
 
-            for line in reader.lines() {
-                if let Ok(line) = line {
-                    if serde_json::from_str::<LogEntry>(&line).is_ok() {
-                        count += 1;
-                    }
+            for line in reader.lines().map_while(Result::ok) {
+                if serde_json::from_str::<LogEntry>(&line).is_ok() {
+                    count += 1;
                 }
             }
 
@@ -0,0 +1,194 @@
+//! Access control for the proxy listener
+//!
+//! Two independent checks: a CIDR allow-list applied when a connection is
+//! accepted, and optional `Proxy-Authorization` basic auth applied to every
+//! request sent to the proxy (including CONNECT).
+
+use crate::proxy_config::AccessConfig;
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use hyper::HeaderMap;
+use std::net::IpAddr;
+use subtle::ConstantTimeEq;
+
+/// Compiled allow-list of client networks
+#[derive(Debug, Clone)]
+pub struct ClientAllowList {
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl ClientAllowList {
+    /// Parse the configured networks, rejecting malformed entries
+    pub fn new(config: &AccessConfig) -> Result<Self> {
+        let networks = config
+            .allowed_clients
+            .iter()
+            .map(|entry| parse_cidr(entry))
+            .collect::<Result<_>>()?;
+        Ok(Self { networks })
+    }
+
+    /// Whether a client at `ip` may connect (an empty list allows everyone)
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.networks.is_empty()
+            || self
+                .networks
+                .iter()
+                .any(|(network, prefix)| in_network(ip, *network, *prefix))
+    }
+}
+
+/// Check the `Proxy-Authorization` header against the configured credentials
+///
+/// Always passes when no username is configured.
+pub fn proxy_auth_ok(config: &AccessConfig, headers: &HeaderMap) -> bool {
+    let Some(username) = &config.username else {
+        return true;
+    };
+
+    let Some(credentials) = headers
+        .get(hyper::header::PROXY_AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            // The auth scheme is case-insensitive (RFC 7235)
+            let (scheme, encoded) = v.trim().split_once(' ')?;
+            scheme.eq_ignore_ascii_case("basic").then_some(encoded)
+        })
+        .and_then(|encoded| {
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded.trim())
+                .ok()
+        })
+    else {
+        return false;
+    };
+
+    let expected = format!("{}:{}", username, config.password.as_deref().unwrap_or(""));
+    credentials.ct_eq(expected.as_bytes()).into()
+}
+
+/// Parse `addr/prefix` or a bare address (a single host)
+fn parse_cidr(entry: &str) -> Result<(IpAddr, u8)> {
+    let (addr, prefix) = match entry.trim().split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (entry.trim(), None),
+    };
+
+    let addr: IpAddr = addr
+        .parse()
+        .with_context(|| format!("Invalid address in allowed_clients: {}", entry))?;
+    let addr = addr.to_canonical();
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+
+    let prefix = match prefix {
+        Some(p) => p
+            .parse()
+            .with_context(|| format!("Invalid prefix length in allowed_clients: {}", entry))?,
+        None => max,
+    };
+    if prefix > max {
+        bail!("Prefix length too long in allowed_clients: {}", entry);
+    }
+
+    Ok((addr, prefix))
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allow_list(entries: &[&str]) -> ClientAllowList {
+        ClientAllowList::new(&AccessConfig {
+            allowed_clients: entries.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_empty_allow_list_allows_everyone() {
+        assert!(allow_list(&[]).allows("203.0.113.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_cidr_matching() {
+        let list = allow_list(&["127.0.0.1", "10.0.0.0/8", "fd00::/8"]);
+
+        assert!(list.allows("127.0.0.1".parse().unwrap()));
+        assert!(!list.allows("127.0.0.2".parse().unwrap()));
+        assert!(list.allows("10.42.1.9".parse().unwrap()));
+        assert!(!list.allows("192.168.1.1".parse().unwrap()));
+        assert!(list.allows("fd12:3456::1".parse().unwrap()));
+        assert!(!list.allows("2001:db8::1".parse().unwrap()));
+        // IPv4 clients seen on a dual-stack socket
+        assert!(list.allows("::ffff:10.1.2.3".parse().unwrap()));
+
+        assert!(allow_list(&["0.0.0.0/0"]).allows("198.51.100.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_invalid_entries_rejected() {
+        for entry in ["10.0.0.0/33", "not-an-ip", "10.0.0.0/x"] {
+            let config = AccessConfig {
+                allowed_clients: vec![entry.to_string()],
+                ..Default::default()
+            };
+            assert!(ClientAllowList::new(&config).is_err(), "{}", entry);
+        }
+    }
+
+    #[test]
+    fn test_proxy_auth() {
+        let config = AccessConfig {
+            username: Some("alice".to_string()),
+            password: Some("secret".to_string()),
+            ..Default::default()
+        };
+
+        let mut headers = HeaderMap::new();
+        assert!(!proxy_auth_ok(&config, &headers));
+
+        // base64("alice:secret")
+        headers.insert(
+            hyper::header::PROXY_AUTHORIZATION,
+            "Basic YWxpY2U6c2VjcmV0".parse().unwrap(),
+        );
+        assert!(proxy_auth_ok(&config, &headers));
+
+        // base64("alice:wrong")
+        headers.insert(
+            hyper::header::PROXY_AUTHORIZATION,
+            "Basic YWxpY2U6d3Jvbmc=".parse().unwrap(),
+        );
+        assert!(!proxy_auth_ok(&config, &headers));
+
+        // The scheme is matched case-insensitively
+        headers.insert(
+            hyper::header::PROXY_AUTHORIZATION,
+            "basic YWxpY2U6c2VjcmV0".parse().unwrap(),
+        );
+        assert!(proxy_auth_ok(&config, &headers));
+        headers.insert(
+            hyper::header::PROXY_AUTHORIZATION,
+            "Bearer YWxpY2U6c2VjcmV0".parse().unwrap(),
+        );
+        assert!(!proxy_auth_ok(&config, &headers));
+
+        assert!(proxy_auth_ok(&AccessConfig::default(), &HeaderMap::new()));
+    }
+}
@@ -0,0 +1,138 @@
+//! Typed extraction of Claude Messages API request fields
+//!
+//! Request bodies are stored as text, so answering "which model" or "which
+//! tools" would otherwise mean parsing JSON nested in the log line. This
+//! module pulls the interesting fields out of a recorded `/v1/messages` body
+//! into an [`ApiRequest`] kept next to it on the request event.
+
+use crate::schema::{ApiRequest, BodyContent, BodyData, ThinkingSettings};
+use serde_json::Value;
+
+/// Extract the request fields from a recorded Messages API body
+///
+/// `full_tools` keeps complete tool definitions instead of names only.
+/// Bodies that are not a complete JSON object are ignored.
+pub fn extract_api_request(body: &BodyData, full_tools: bool) -> Option<ApiRequest> {
+    let BodyContent::Text { data } = &body.content else {
+        return None;
+    };
+    let Value::Object(json) = serde_json::from_str(data).ok()? else {
+        return None;
+    };
+
+    let tools = json.get("tools").and_then(Value::as_array);
+    let tool_names = tools
+        .into_iter()
+        .flatten()
+        .filter_map(|tool| tool.get("name").and_then(Value::as_str))
+        .map(String::from)
+        .collect();
+
+    Some(ApiRequest {
+        model: json.get("model").and_then(Value::as_str).map(String::from),
+        max_tokens: json.get("max_tokens").and_then(Value::as_u64),
+        stream: json.get("stream").and_then(Value::as_bool).unwrap_or(false),
+        message_count: json
+            .get("messages")
+            .and_then(Value::as_array)
+            .map_or(0, Vec::len),
+        system_prompt_chars: json.get("system").map_or(0, system_prompt_chars),
+        tool_names,
+        tools: tools.filter(|_| full_tools).cloned(),
+        thinking: json
+            .get("thinking")
+            .and_then(|t| serde_json::from_value::<ThinkingSettings>(t.clone()).ok()),
+    })
+}
+
+/// Characters of system prompt text, given as a string or a list of text blocks
+fn system_prompt_chars(system: &Value) -> usize {
+    match system {
+        Value::String(text) => text.chars().count(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|block| block.get("text").and_then(Value::as_str))
+            .map(|text| text.chars().count())
+            .sum(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn body(value: &Value) -> BodyData {
+        BodyData::from_bytes(
+            value.to_string().as_bytes(),
+            None,
+            Some("application/json".to_string()),
+            usize::MAX,
+        )
+    }
+
+    fn request() -> Value {
+        json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 32000,
+            "stream": true,
+            "system": [
+                {"type": "text", "text": "You are Claude."},
+                {"type": "text", "text": "Be concise.", "cache_control": {"type": "ephemeral"}}
+            ],
+            "messages": [
+                {"role": "user", "content": "hi"},
+                {"role": "assistant", "content": "hello"},
+                {"role": "user", "content": "list files"}
+            ],
+            "tools": [
+                {"name": "Bash", "description": "Run a command", "input_schema": {"type": "object"}},
+                {"name": "Read", "description": "Read a file", "input_schema": {"type": "object"}}
+            ],
+            "thinking": {"type": "enabled", "budget_tokens": 10000}
+        })
+    }
+
+    #[test]
+    fn test_extract_messages_request() {
+        let api = extract_api_request(&body(&request()), false).unwrap();
+
+        assert_eq!(api.model.as_deref(), Some("claude-sonnet-4-5"));
+        assert_eq!(api.max_tokens, Some(32000));
+        assert!(api.stream);
+        assert_eq!(api.message_count, 3);
+        assert_eq!(api.system_prompt_chars, "You are Claude.".len() + "Be concise.".len());
+        assert_eq!(api.tool_names, vec!["Bash", "Read"]);
+        assert!(api.tools.is_none());
+        assert_eq!(
+            api.thinking,
+            Some(ThinkingSettings {
+                kind: "enabled".to_string(),
+                budget_tokens: Some(10000),
+            })
+        );
+    }
+
+    #[test]
+    fn test_full_tool_definitions_kept_when_asked() {
+        let api = extract_api_request(&body(&request()), true).unwrap();
+        let tools = api.tools.unwrap();
+        assert_eq!(tools.len(), 2);
+        assert_eq!(tools[0]["description"], "Run a command");
+    }
+
+    #[test]
+    fn test_minimal_and_non_json_bodies() {
+        let api = extract_api_request(&body(&json!({"system": "héllo", "messages": []})), false).unwrap();
+        assert_eq!(api.model, None);
+        assert!(!api.stream);
+        assert_eq!(api.system_prompt_chars, 5);
+        assert!(api.tool_names.is_empty());
+        assert!(api.thinking.is_none());
+
+        let text = BodyData::from_bytes(b"not json", None, None, 1024);
+        assert!(extract_api_request(&text, false).is_none());
+        assert!(extract_api_request(&body(&json!([1, 2])), false).is_none());
+    }
+}
@@ -0,0 +1,205 @@
+//! Content-addressed storage for large bodies
+//!
+//! Claude Code resends the whole conversation on every turn, so the same
+//! multi-megabyte bodies would be written to the daily log again and again.
+//! Bodies over the configured threshold are written once to
+//! `blobs/<hash prefix>/<hash>` next to the log files, and the log line keeps
+//! a [`BodyContent::BlobRef`] instead. Identical bodies hash the same and are
+//! stored once.
+//!
+//! A blob holds the serialized [`BodyContent`] it replaced, so resolving a
+//! reference restores the entry exactly as it would have been logged.
+
+use crate::schema::{BodyContent, BodyData, LogEntry, LogEvent};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory of blobs belonging to one logs directory
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    dir: PathBuf,
+}
+
+impl BlobStore {
+    /// Blob store for the logs in `logs_dir`
+    pub fn new(logs_dir: &Path) -> Self {
+        Self {
+            dir: logs_dir.join("blobs"),
+        }
+    }
+
+    /// Blob store for the directory a log file lives in
+    pub fn for_log_file(log_file: &Path) -> Self {
+        Self::new(log_file.parent().unwrap_or(Path::new(".")))
+    }
+
+    /// Store `data`, returning its hash; existing blobs are not rewritten
+    pub fn put(&self, data: &[u8]) -> io::Result<String> {
+        let hash = hex(&Sha256::digest(data));
+        let path = self.path(&hash);
+        if path.exists() {
+            return Ok(hash);
+        }
+
+        let dir = path.parent().expect("blob paths have a parent");
+        fs::create_dir_all(dir)?;
+
+        // Write under a unique name, then rename, so concurrent writers and
+        // readers never see a partial blob
+        let tmp = dir.join(format!(".{}.{}", hash, uuid::Uuid::new_v4()));
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &path).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })?;
+
+        Ok(hash)
+    }
+
+    /// Read the blob with the given hash
+    pub fn get(&self, hash: &str) -> io::Result<Vec<u8>> {
+        if hash.len() < 3 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid blob hash: {}", hash),
+            ));
+        }
+        fs::read(self.path(hash))
+    }
+
+    /// Move the body content to the store when it is larger than `threshold`
+    ///
+    /// Falls back to keeping the body inline if the blob cannot be written.
+    pub fn offload(&self, mut body: BodyData, threshold: usize) -> BodyData {
+        let inline = matches!(
+            body.content,
+            BodyContent::Empty | BodyContent::Omitted | BodyContent::BlobRef { .. }
+        );
+        if inline || body.stored_size_bytes <= threshold {
+            return body;
+        }
+
+        let stored = serde_json::to_vec(&body.content)
+            .map_err(io::Error::other)
+            .and_then(|data| self.put(&data));
+        match stored {
+            Ok(hash) => body.content = BodyContent::BlobRef { hash },
+            Err(e) => tracing::warn!("Failed to store body blob, keeping it inline: {}", e),
+        }
+        body
+    }
+
+    /// Replace a blob reference with the content it points to
+    ///
+    /// References whose blob is missing or unreadable are left as they are.
+    pub fn resolve(&self, body: &mut BodyData) {
+        let BodyContent::BlobRef { hash } = &body.content else {
+            return;
+        };
+        if let Some(content) = self
+            .get(hash)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+        {
+            body.content = content;
+        }
+    }
+
+    /// Resolve the bodies of a request or response entry
+    pub fn resolve_entry(&self, entry: &mut LogEntry) {
+        match &mut entry.event {
+            LogEvent::ProxyRequest(req) => self.resolve(&mut req.body),
+            LogEvent::ProxyResponse(resp) => self.resolve(&mut resp.body),
+            _ => {}
+        }
+    }
+
+    fn path(&self, hash: &str) -> PathBuf {
+        self.dir.join(&hash[..2]).join(hash)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn text_body(text: &str) -> BodyData {
+        BodyData::from_bytes(text.as_bytes(), None, Some("text/plain".to_string()), usize::MAX)
+    }
+
+    #[test]
+    fn test_put_is_content_addressed() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = BlobStore::new(temp_dir.path());
+
+        let first = store.put(b"same body").unwrap();
+        let second = store.put(b"same body").unwrap();
+        let other = store.put(b"other body").unwrap();
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert_eq!(first.len(), 64);
+        assert_eq!(store.get(&first).unwrap(), b"same body");
+
+        // One blob per distinct body, no leftover temp files
+        let files: Vec<_> = walk(&temp_dir.path().join("blobs"));
+        assert_eq!(files.len(), 2);
+    }
+
+    #[test]
+    fn test_offload_and_resolve_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = BlobStore::new(temp_dir.path());
+
+        let body = text_body(&"x".repeat(100));
+        let offloaded = store.offload(body.clone(), 10);
+        assert!(matches!(offloaded.content, BodyContent::BlobRef { .. }));
+        assert_eq!(offloaded.stored_size_bytes, body.stored_size_bytes);
+
+        let mut resolved = offloaded;
+        store.resolve(&mut resolved);
+        match resolved.content {
+            BodyContent::Text { data } => assert_eq!(data, "x".repeat(100)),
+            other => panic!("Expected Text content, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_small_bodies_stay_inline() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = BlobStore::new(temp_dir.path());
+
+        let body = store.offload(text_body("short"), 1024);
+        assert!(matches!(body.content, BodyContent::Text { .. }));
+        assert!(!temp_dir.path().join("blobs").exists());
+    }
+
+    #[test]
+    fn test_missing_blob_left_unresolved() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = BlobStore::new(temp_dir.path());
+
+        let mut body = text_body("x");
+        body.content = BodyContent::BlobRef { hash: "ab".repeat(32) };
+        store.resolve(&mut body);
+        assert!(matches!(body.content, BodyContent::BlobRef { .. }));
+
+        assert!(store.get("../../etc/passwd").is_err());
+    }
+
+    fn walk(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .unwrap()
+            .flat_map(|e| {
+                let path = e.unwrap().path();
+                if path.is_dir() { walk(&path) } else { vec![path] }
+            })
+            .collect()
+    }
+}
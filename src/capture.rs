@@ -0,0 +1,240 @@
+//! Capture filtering for intercepted exchanges
+//!
+//! Decides from the request path, method and response status whether an
+//! exchange is recorded in full, recorded as metadata only (no bodies), or
+//! not recorded at all. Filtering never affects forwarding: every request is
+//! still sent upstream and its response returned to the client.
+
+use crate::proxy_config::FilteringConfig;
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+
+/// How much of an exchange gets recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    /// Request and response events with bodies
+    Full,
+    /// Request and response events without bodies
+    Metadata,
+    /// Nothing is recorded
+    Skip,
+}
+
+/// Compiled form of the capture settings in [`FilteringConfig`]
+#[derive(Debug, Clone)]
+pub struct CaptureFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    methods: Vec<String>,
+    statuses: Vec<(u16, u16)>,
+    uncaptured: Capture,
+}
+
+impl CaptureFilter {
+    /// Compile the filter, rejecting invalid patterns or status ranges
+    pub fn new(config: &FilteringConfig) -> Result<Self> {
+        let compile = |patterns: &[String]| -> Result<Vec<Regex>> {
+            patterns.iter().map(|p| compile_pattern(p)).collect()
+        };
+
+        Ok(Self {
+            include: compile(&config.capture_patterns)?,
+            exclude: compile(&config.exclude_patterns)?,
+            methods: config
+                .capture_methods
+                .iter()
+                .map(|m| m.to_ascii_uppercase())
+                .collect(),
+            statuses: config
+                .capture_status
+                .iter()
+                .map(|s| parse_status(s))
+                .collect::<Result<_>>()?,
+            uncaptured: if config.record_uncaptured_metadata {
+                Capture::Metadata
+            } else {
+                Capture::Skip
+            },
+        })
+    }
+
+    /// Decide from the request alone
+    ///
+    /// Returns None when status filters are configured and the request
+    /// matched, meaning the decision has to wait for the response.
+    pub fn decide_request(&self, method: &str, path: &str) -> Option<Capture> {
+        if !self.request_matches(method, path) {
+            Some(self.uncaptured)
+        } else if self.statuses.is_empty() {
+            Some(Capture::Full)
+        } else {
+            None
+        }
+    }
+
+    /// Decide once the response status is known
+    pub fn decide_response(&self, method: &str, path: &str, status: u16) -> Capture {
+        if self.request_matches(method, path) && self.status_matches(status) {
+            Capture::Full
+        } else {
+            self.uncaptured
+        }
+    }
+
+    fn request_matches(&self, method: &str, path: &str) -> bool {
+        let method_ok = self.methods.is_empty() || self.methods.iter().any(|m| m == method);
+        let included = self.include.is_empty() || self.include.iter().any(|re| re.is_match(path));
+        let excluded = self.exclude.iter().any(|re| re.is_match(path));
+        method_ok && included && !excluded
+    }
+
+    fn status_matches(&self, status: u16) -> bool {
+        self.statuses.is_empty()
+            || self
+                .statuses
+                .iter()
+                .any(|(low, high)| (*low..=*high).contains(&status))
+    }
+}
+
+/// Compile a path pattern: `re:` prefix for a regex, otherwise a glob
+///
+/// In globs `*` matches within one path segment, `**` across segments and
+/// `?` a single character. Globs must match the whole path.
+fn compile_pattern(pattern: &str) -> Result<Regex> {
+    if let Some(re) = pattern.strip_prefix("re:") {
+        return Regex::new(re).with_context(|| format!("Invalid capture regex: {}", re));
+    }
+
+    let mut re = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                re.push_str(".*");
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+
+    Regex::new(&re).with_context(|| format!("Invalid capture glob: {}", pattern))
+}
+
+/// Parse a status filter: exact ("429"), class ("5xx") or range ("400-499")
+fn parse_status(spec: &str) -> Result<(u16, u16)> {
+    let spec = spec.trim();
+    let parse = |s: &str| -> Result<u16> {
+        s.parse()
+            .with_context(|| format!("Invalid status filter: {}", spec))
+    };
+
+    let range = if let Some(class) = spec.to_ascii_lowercase().strip_suffix("xx") {
+        let class = parse(class)?;
+        (class * 100, class * 100 + 99)
+    } else if let Some((low, high)) = spec.split_once('-') {
+        (parse(low.trim())?, parse(high.trim())?)
+    } else {
+        let status = parse(spec)?;
+        (status, status)
+    };
+
+    if range.0 > range.1 || range.1 > 999 {
+        bail!("Invalid status filter: {}", spec);
+    }
+    Ok(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(config: FilteringConfig) -> CaptureFilter {
+        CaptureFilter::new(&config).unwrap()
+    }
+
+    #[test]
+    fn test_default_captures_everything() {
+        let f = filter(FilteringConfig::default());
+        assert_eq!(f.decide_request("GET", "/anything"), Some(Capture::Full));
+    }
+
+    #[test]
+    fn test_glob_patterns() {
+        let f = filter(FilteringConfig {
+            capture_patterns: vec!["/v1/messages*".to_string(), "/api/**/usage".to_string()],
+            ..Default::default()
+        });
+
+        assert_eq!(f.decide_request("POST", "/v1/messages"), Some(Capture::Full));
+        assert_eq!(f.decide_request("POST", "/v1/messages/count_tokens"), Some(Capture::Skip));
+        assert_eq!(f.decide_request("GET", "/api/org/42/usage"), Some(Capture::Full));
+        assert_eq!(f.decide_request("GET", "/api/event_logging/batch"), Some(Capture::Skip));
+    }
+
+    #[test]
+    fn test_regex_and_exclusions() {
+        let f = filter(FilteringConfig {
+            capture_patterns: vec!["re:^/(v1|api)/".to_string()],
+            exclude_patterns: vec!["/api/event_logging/**".to_string()],
+            record_uncaptured_metadata: true,
+            ..Default::default()
+        });
+
+        assert_eq!(f.decide_request("POST", "/v1/messages"), Some(Capture::Full));
+        assert_eq!(
+            f.decide_request("POST", "/api/event_logging/batch"),
+            Some(Capture::Metadata)
+        );
+        assert_eq!(f.decide_request("GET", "/health"), Some(Capture::Metadata));
+    }
+
+    #[test]
+    fn test_method_filter() {
+        let f = filter(FilteringConfig {
+            capture_methods: vec!["post".to_string()],
+            ..Default::default()
+        });
+
+        assert_eq!(f.decide_request("POST", "/v1/messages"), Some(Capture::Full));
+        assert_eq!(f.decide_request("GET", "/v1/models"), Some(Capture::Skip));
+    }
+
+    #[test]
+    fn test_status_filter_defers_decision() {
+        let f = filter(FilteringConfig {
+            capture_methods: vec!["POST".to_string()],
+            capture_status: vec!["4xx".to_string(), "500-599".to_string()],
+            ..Default::default()
+        });
+
+        assert_eq!(f.decide_request("POST", "/v1/messages"), None);
+        assert_eq!(f.decide_request("GET", "/v1/messages"), Some(Capture::Skip));
+        assert_eq!(f.decide_response("POST", "/v1/messages", 429), Capture::Full);
+        assert_eq!(f.decide_response("POST", "/v1/messages", 529), Capture::Full);
+        assert_eq!(f.decide_response("POST", "/v1/messages", 200), Capture::Skip);
+    }
+
+    #[test]
+    fn test_invalid_filters_rejected() {
+        for config in [
+            FilteringConfig {
+                capture_patterns: vec!["re:(".to_string()],
+                ..Default::default()
+            },
+            FilteringConfig {
+                capture_status: vec!["2yy".to_string()],
+                ..Default::default()
+            },
+            FilteringConfig {
+                capture_status: vec!["500-400".to_string()],
+                ..Default::default()
+            },
+        ] {
+            assert!(CaptureFilter::new(&config).is_err());
+        }
+    }
+}
@@ -12,6 +12,9 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+/// Certificate chain and private key for a single host
+type HostCertificate = (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>);
+
 /// Manages TLS certificates for the MITM proxy
 pub struct CertificateManager {
     /// Root CA certificate for signing host certificates
@@ -19,7 +22,7 @@ pub struct CertificateManager {
     /// Root CA key pair
     root_ca_keypair: KeyPair,
     /// Cache of generated host certificates
-    cache: Arc<RwLock<HashMap<String, (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>>>,
+    cache: Arc<RwLock<HashMap<String, HostCertificate>>>,
     /// Directory to store certificates
     _cert_dir: PathBuf,
 }
@@ -63,7 +63,7 @@ fn get_claude_config_path() -> Result<PathBuf> {
     let home = std::env::var("HOME")
         .ok()
         .map(PathBuf::from)
-        .or_else(|| dirs::home_dir())
+        .or_else(dirs::home_dir)
         .context("Could not determine home directory")?;
     Ok(home.join(".claude.json"))
 }
@@ -73,7 +73,7 @@ fn get_claude_settings_path() -> Result<PathBuf> {
     let home = std::env::var("HOME")
         .ok()
         .map(PathBuf::from)
-        .or_else(|| dirs::home_dir())
+        .or_else(dirs::home_dir)
         .context("Could not determine home directory")?;
     Ok(home.join(".claude").join("settings.json"))
 }
@@ -173,7 +173,7 @@ pub fn install_claude_config(quiet: bool) -> Result<()> {
     };
 
     for hook_type in hook_types {
-        let entries = settings.hooks.entry(hook_type.to_string()).or_insert_with(Vec::new);
+        let entries = settings.hooks.entry(hook_type.to_string()).or_default();
 
         // Check if local-logger hook already exists in this hook type
         let has_local_logger = entries.iter().any(|entry| {
@@ -0,0 +1,327 @@
+//! Delta encoding of Claude request bodies
+//!
+//! Each turn of a conversation resends every earlier message, so successive
+//! `/v1/messages` bodies share a growing `messages` prefix. The encoder
+//! remembers the last request of each conversation and records the next one
+//! as a [`BodyContent::Delta`]: the messages after the shared prefix, the
+//! other top-level fields that changed, and the parent request id.
+//! [`crate::schema::reconstruct_body`] rebuilds the full body.
+//!
+//! Conversations are told apart by their session and first message, so two
+//! sessions opening with the same prompt do not interleave. A wrong guess only
+//! costs compression, never correctness, since the shared prefix is compared
+//! message by message. Parents are only referenced within the same day, so
+//! every daily log file can be reconstructed on its own.
+
+use crate::schema::{BodyContent, BodyData};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// Conversations remembered at once; the least recently started are forgotten
+const MAX_CONVERSATIONS: usize = 256;
+
+/// Remembers the latest request body of recent conversations
+///
+/// Only digests of each message and top-level field are kept, not the bodies
+/// themselves, so a remembered conversation costs a few bytes per message.
+#[derive(Clone, Default)]
+pub struct DeltaEncoder {
+    inner: Arc<Mutex<Conversations>>,
+}
+
+#[derive(Default)]
+struct Conversations {
+    latest: HashMap<u64, Turn>,
+    order: VecDeque<u64>,
+}
+
+/// Digests of one request body of a conversation
+struct Turn {
+    request_id: Uuid,
+    date: String,
+    /// Digest of every message, in order
+    messages: Vec<Digest256>,
+    /// Digest of every other top-level field
+    fields: HashMap<String, Digest256>,
+}
+
+type Digest256 = [u8; 32];
+
+impl Turn {
+    fn new(request_id: Uuid, date: &str, body: &Map<String, Value>) -> Option<Self> {
+        let messages = body.get("messages")?.as_array()?.iter().map(digest).collect();
+        let fields = body
+            .iter()
+            .filter(|(field, _)| *field != "messages")
+            .map(|(field, value)| (field.clone(), digest(value)))
+            .collect();
+        Some(Self {
+            request_id,
+            date: date.to_string(),
+            messages,
+            fields,
+        })
+    }
+}
+
+/// SHA-256 of a value's serialized form, standing in for the value when
+/// comparing against the next turn
+fn digest(value: &Value) -> Digest256 {
+    Sha256::digest(value.to_string().as_bytes()).into()
+}
+
+/// A request body to remember once its log entry has been written
+///
+/// Remembering only written bodies keeps later deltas from pointing at a
+/// parent that is missing from the log.
+pub struct PendingTurn {
+    key: u64,
+    turn: Turn,
+}
+
+impl DeltaEncoder {
+    /// Encode a request body against the previous turn of its conversation
+    ///
+    /// Bodies that are not a complete JSON object with `messages` are
+    /// returned unchanged. Pass the returned turn to [`DeltaEncoder::remember`]
+    /// once the body is in the log, so the next turn can build on it.
+    pub fn encode(&self, request_id: Uuid, session_id: &str, date: &str, body: BodyData) -> (BodyData, Option<PendingTurn>) {
+        let BodyContent::Text { data } = &body.content else {
+            return (body, None);
+        };
+        let Ok(Value::Object(current)) = serde_json::from_str::<Value>(data) else {
+            return (body, None);
+        };
+        let Some(key) = conversation_key(session_id, &current) else {
+            return (body, None);
+        };
+        let Some(turn) = Turn::new(request_id, date, &current) else {
+            return (body, None);
+        };
+
+        let delta = self
+            .inner
+            .lock()
+            .unwrap()
+            .latest
+            .get(&key)
+            .filter(|previous| previous.date == date)
+            .and_then(|previous| diff(previous, &turn, &current));
+        let pending = Some(PendingTurn { key, turn });
+
+        match delta {
+            Some(content) => {
+                let stored_size_bytes = serde_json::to_string(&content).map_or(0, |s| s.len());
+                let body = BodyData {
+                    stored_size_bytes,
+                    content,
+                    ..body
+                };
+                (body, pending)
+            }
+            None => (body, pending),
+        }
+    }
+
+    /// Make a written body the parent of its conversation's next turn
+    pub fn remember(&self, pending: PendingTurn) {
+        self.inner.lock().unwrap().remember(pending.key, pending.turn);
+    }
+}
+
+impl Conversations {
+    fn remember(&mut self, key: u64, turn: Turn) {
+        if self.latest.insert(key, turn).is_none() {
+            self.order.push_back(key);
+            if self.order.len() > MAX_CONVERSATIONS {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.latest.remove(&oldest);
+                }
+            }
+        }
+    }
+}
+
+/// Hash of the session and first message, shared by every turn of a conversation
+fn conversation_key(session_id: &str, body: &Map<String, Value>) -> Option<u64> {
+    let first = body.get("messages")?.as_array()?.first()?;
+    let mut hasher = DefaultHasher::new();
+    session_id.hash(&mut hasher);
+    first.to_string().hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Describe `current` relative to `previous`, if they share any messages
+///
+/// `turn` holds the digests of `current`.
+fn diff(previous: &Turn, turn: &Turn, current: &Map<String, Value>) -> Option<BodyContent> {
+    let messages = current.get("messages")?.as_array()?;
+
+    let prefix_messages = previous
+        .messages
+        .iter()
+        .zip(&turn.messages)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if prefix_messages == 0 {
+        return None;
+    }
+
+    let changed = current
+        .iter()
+        .filter(|(field, _)| previous.fields.get(*field) != turn.fields.get(*field))
+        .map(|(field, value)| (field.clone(), value.clone()))
+        .collect();
+    let removed = previous
+        .fields
+        .keys()
+        .filter(|field| !current.contains_key(*field))
+        .cloned()
+        .collect();
+
+    Some(BodyContent::Delta {
+        parent_request_id: previous.request_id,
+        prefix_messages,
+        messages: messages[prefix_messages..].to_vec(),
+        changed,
+        removed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::reconstruct_body;
+    use serde_json::json;
+
+    fn body(value: &Value) -> BodyData {
+        BodyData::from_bytes(
+            value.to_string().as_bytes(),
+            None,
+            Some("application/json".to_string()),
+            usize::MAX,
+        )
+    }
+
+    /// Encode a body and remember it, as if its entry was written
+    fn encode(encoder: &DeltaEncoder, request_id: Uuid, session_id: &str, date: &str, body: BodyData) -> BodyData {
+        let (body, pending) = encoder.encode(request_id, session_id, date, body);
+        if let Some(pending) = pending {
+            encoder.remember(pending);
+        }
+        body
+    }
+
+    fn turn(n: usize) -> Value {
+        let messages: Vec<Value> = (0..n)
+            .map(|i| json!({"role": if i % 2 == 0 { "user" } else { "assistant" }, "content": format!("message {} {}", i, "lorem ipsum ".repeat(20))}))
+            .collect();
+        json!({"model": "claude-sonnet-4", "system": "You are helpful", "messages": messages})
+    }
+
+    #[test]
+    fn test_successive_turns_become_deltas() {
+        let encoder = DeltaEncoder::default();
+        let first_id = Uuid::new_v4();
+        let second_id = Uuid::new_v4();
+        let third_id = Uuid::new_v4();
+
+        let first = encode(&encoder, first_id, "session", "2025-01-01", body(&turn(1)));
+        assert!(matches!(first.content, BodyContent::Text { .. }));
+
+        let second = encode(&encoder, second_id, "session", "2025-01-01", body(&turn(3)));
+        match &second.content {
+            BodyContent::Delta {
+                parent_request_id,
+                prefix_messages,
+                messages,
+                changed,
+                removed,
+            } => {
+                assert_eq!(*parent_request_id, first_id);
+                assert_eq!(*prefix_messages, 1);
+                assert_eq!(messages.len(), 2);
+                assert!(changed.is_empty());
+                assert!(removed.is_empty());
+            }
+            other => panic!("Expected Delta content, got {:?}", other),
+        }
+        assert!(second.stored_size_bytes < body(&turn(3)).stored_size_bytes);
+
+        // Model switch mid-conversation, system prompt dropped
+        let mut third_value = turn(5);
+        third_value["model"] = json!("claude-opus-4");
+        third_value.as_object_mut().unwrap().remove("system");
+        let third = encode(&encoder, third_id, "session", "2025-01-01", body(&third_value));
+
+        let bodies: HashMap<Uuid, BodyData> = [(first_id, first), (second_id, second)].into();
+        let lookup = |id: &Uuid| bodies.get(id).cloned();
+        assert_eq!(reconstruct_body(&bodies[&second_id], lookup).unwrap(), turn(3));
+        assert_eq!(reconstruct_body(&third, lookup).unwrap(), third_value);
+    }
+
+    #[test]
+    fn test_unrelated_or_new_day_requests_stored_in_full() {
+        let encoder = DeltaEncoder::default();
+
+        encode(&encoder, Uuid::new_v4(), "session", "2025-01-01", body(&turn(2)));
+        let next_day = encode(&encoder, Uuid::new_v4(), "session", "2025-01-02", body(&turn(4)));
+        assert!(matches!(next_day.content, BodyContent::Text { .. }));
+
+        let other = json!({"model": "claude-sonnet-4", "messages": [{"role": "user", "content": "unrelated"}]});
+        let unrelated = encode(&encoder, Uuid::new_v4(), "session", "2025-01-02", body(&other));
+        assert!(matches!(unrelated.content, BodyContent::Text { .. }));
+
+        let not_messages = encode(&encoder, Uuid::new_v4(), "session", "2025-01-02", body(&json!({"events": []})));
+        assert!(matches!(not_messages.content, BodyContent::Text { .. }));
+    }
+
+    #[test]
+    fn test_missing_parent_cannot_be_reconstructed() {
+        let encoder = DeltaEncoder::default();
+        encode(&encoder, Uuid::new_v4(), "session", "2025-01-01", body(&turn(1)));
+        let delta = encode(&encoder, Uuid::new_v4(), "session", "2025-01-01", body(&turn(3)));
+
+        assert!(reconstruct_body(&delta, |_| None).is_none());
+    }
+
+    #[test]
+    fn test_unwritten_turn_not_used_as_parent() {
+        let encoder = DeltaEncoder::default();
+        let first_id = Uuid::new_v4();
+        encode(&encoder, first_id, "session", "2025-01-01", body(&turn(1)));
+
+        // Its entry was never written, so it is not remembered
+        let (_, _unwritten) = encoder.encode(Uuid::new_v4(), "session", "2025-01-01", body(&turn(3)));
+
+        let third = encode(&encoder, Uuid::new_v4(), "session", "2025-01-01", body(&turn(5)));
+        match third.content {
+            BodyContent::Delta { parent_request_id, .. } => assert_eq!(parent_request_id, first_id),
+            other => panic!("Expected Delta content, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_sessions_with_same_first_message_kept_apart() {
+        let encoder = DeltaEncoder::default();
+        let first_a = Uuid::new_v4();
+        let first_b = Uuid::new_v4();
+
+        encode(&encoder, first_a, "session-a", "2025-01-01", body(&turn(1)));
+        encode(&encoder, first_b, "session-b", "2025-01-01", body(&turn(1)));
+
+        let next_a = encode(&encoder, Uuid::new_v4(), "session-a", "2025-01-01", body(&turn(3)));
+        let next_b = encode(&encoder, Uuid::new_v4(), "session-b", "2025-01-01", body(&turn(3)));
+        for (next, parent) in [(next_a, first_a), (next_b, first_b)] {
+            match next.content {
+                BodyContent::Delta { parent_request_id, .. } => assert_eq!(parent_request_id, parent),
+                other => panic!("Expected Delta content, got {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Host rules deciding which CONNECT tunnels are decrypted (MITM)
+//!
+//! Rules are written as:
+//! - `api.anthropic.com` — that exact host, any port
+//! - `*.anthropic.com` — any subdomain of anthropic.com (not the bare domain)
+//! - `*` — any host
+//! - any of the above with `:port` appended to restrict it to one port
+//!
+//! `never_intercept` rules always win over `target_hosts`.
+
+use crate::proxy_config::FilteringConfig;
+use std::fmt;
+
+/// Outcome of evaluating the host rules for one CONNECT request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterceptDecision {
+    /// Whether the tunnel is decrypted
+    pub intercept: bool,
+    /// Why, for the audit trail
+    pub reason: InterceptReason,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterceptReason {
+    /// Matched an entry in `never_intercept`
+    NeverIntercept(String),
+    /// Matched an entry in `target_hosts`
+    TargetHost(String),
+    /// `target_hosts` is empty, so every host not excluded is intercepted
+    NoTargetHosts,
+    /// No `target_hosts` entry matched
+    NoMatch,
+}
+
+impl fmt::Display for InterceptReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NeverIntercept(rule) => write!(f, "never_intercept rule '{}'", rule),
+            Self::TargetHost(rule) => write!(f, "target_hosts rule '{}'", rule),
+            Self::NoTargetHosts => write!(f, "no target_hosts configured"),
+            Self::NoMatch => write!(f, "no target_hosts rule matched"),
+        }
+    }
+}
+
+/// Decide whether to intercept a CONNECT to `authority` (`host[:port]`)
+pub fn decide(filtering: &FilteringConfig, authority: &str) -> InterceptDecision {
+    let (host, port) = split_authority(authority);
+    let host = normalize_host(host);
+    let port = port.unwrap_or(443);
+
+    let matching = |rules: &[String]| {
+        rules
+            .iter()
+            .find(|rule| rule_matches(rule, &host, port))
+            .cloned()
+    };
+
+    let (intercept, reason) = if let Some(rule) = matching(&filtering.never_intercept) {
+        (false, InterceptReason::NeverIntercept(rule))
+    } else if filtering.target_hosts.is_empty() {
+        (true, InterceptReason::NoTargetHosts)
+    } else if let Some(rule) = matching(&filtering.target_hosts) {
+        (true, InterceptReason::TargetHost(rule))
+    } else {
+        (false, InterceptReason::NoMatch)
+    };
+
+    InterceptDecision { intercept, reason }
+}
+
+fn rule_matches(rule: &str, host: &str, port: u16) -> bool {
+    let (rule_host, rule_port) = split_authority(rule.trim());
+    if rule_port.is_some_and(|p| p != port) {
+        return false;
+    }
+
+    let rule_host = normalize_host(rule_host);
+    if rule_host == "*" {
+        return true;
+    }
+    match rule_host.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        None => host == rule_host,
+    }
+}
+
+/// Split `host[:port]`, leaving IPv6 literals like `[::1]:443` intact
+fn split_authority(authority: &str) -> (&str, Option<u16>) {
+    if let Some((host, port)) = authority.rsplit_once(':') {
+        if !host.is_empty() && (!host.contains(':') || host.ends_with(']')) {
+            if let Ok(port) = port.parse() {
+                return (host, Some(port));
+            }
+        }
+    }
+    (authority, None)
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filtering(target_hosts: &[&str], never_intercept: &[&str]) -> FilteringConfig {
+        FilteringConfig {
+            target_hosts: target_hosts.iter().map(|s| s.to_string()).collect(),
+            never_intercept: never_intercept.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_exact_host() {
+        let config = filtering(&["api.anthropic.com"], &[]);
+
+        assert!(decide(&config, "api.anthropic.com:443").intercept);
+        assert!(decide(&config, "API.Anthropic.com.:443").intercept);
+        assert!(!decide(&config, "evil-api.anthropic.com.attacker.net:443").intercept);
+        assert!(!decide(&config, "anthropic.com:443").intercept);
+    }
+
+    #[test]
+    fn test_suffix_rule() {
+        let config = filtering(&["*.anthropic.com"], &[]);
+
+        assert!(decide(&config, "api.anthropic.com:443").intercept);
+        assert!(decide(&config, "a.b.anthropic.com:443").intercept);
+        assert!(!decide(&config, "anthropic.com:443").intercept);
+        assert!(!decide(&config, "notanthropic.com:443").intercept);
+        assert!(!decide(&config, "api.anthropic.com.attacker.net:443").intercept);
+    }
+
+    #[test]
+    fn test_port_specific_rule() {
+        let config = filtering(&["localhost:8443", "[::1]:9443"], &[]);
+
+        assert!(decide(&config, "localhost:8443").intercept);
+        assert!(!decide(&config, "localhost:443").intercept);
+        assert!(decide(&config, "[::1]:9443").intercept);
+        assert!(!decide(&config, "[::1]:443").intercept);
+    }
+
+    #[test]
+    fn test_never_intercept_wins() {
+        let config = filtering(&["*.anthropic.com"], &["statsig.anthropic.com"]);
+
+        let decision = decide(&config, "statsig.anthropic.com:443");
+        assert!(!decision.intercept);
+        assert_eq!(
+            decision.reason,
+            InterceptReason::NeverIntercept("statsig.anthropic.com".to_string())
+        );
+
+        let decision = decide(&config, "api.anthropic.com:443");
+        assert_eq!(
+            decision.reason,
+            InterceptReason::TargetHost("*.anthropic.com".to_string())
+        );
+    }
+
+    #[test]
+    fn test_empty_target_hosts_intercepts_everything_not_excluded() {
+        let config = filtering(&[], &["*.apple.com"]);
+
+        let decision = decide(&config, "example.com:443");
+        assert!(decision.intercept);
+        assert_eq!(decision.reason, InterceptReason::NoTargetHosts);
+        assert!(!decide(&config, "push.apple.com:443").intercept);
+    }
+
+    #[test]
+    fn test_reason_display() {
+        let config = filtering(&["api.anthropic.com"], &[]);
+        let decision = decide(&config, "example.com:443");
+        assert_eq!(decision.reason.to_string(), "no target_hosts rule matched");
+    }
+}
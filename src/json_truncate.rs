@@ -0,0 +1,172 @@
+//! Structure-aware truncation of oversized JSON bodies
+//!
+//! Instead of cutting the serialized text at a byte offset, the document is
+//! shrunk until it fits: long strings are elided, long arrays keep their
+//! first and last items around an "N items omitted" marker, and `messages`
+//! arrays keep their most recent turns. The result is always valid JSON with
+//! the original structure, so a capped Claude request can still be parsed.
+//!
+//! Limits are tightened step by step until the output fits `max_size`.
+
+use serde_json::Value;
+
+/// How aggressively to shrink at one step
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    /// Characters kept from a long string
+    string_chars: usize,
+    /// Items kept from the start of a long array
+    head_items: usize,
+    /// Items kept from the end of a long array
+    tail_items: usize,
+    /// Most recent entries kept from a `messages` array
+    messages: usize,
+}
+
+const STEPS: &[Limits] = &[
+    Limits { string_chars: 4096, head_items: 20, tail_items: 10, messages: 20 },
+    Limits { string_chars: 1024, head_items: 10, tail_items: 5, messages: 10 },
+    Limits { string_chars: 256, head_items: 5, tail_items: 2, messages: 4 },
+    Limits { string_chars: 64, head_items: 2, tail_items: 1, messages: 2 },
+    Limits { string_chars: 16, head_items: 1, tail_items: 0, messages: 1 },
+];
+
+/// Shrink `value` until its serialization fits in `max_size` bytes
+///
+/// Returns None when even the tightest limits do not fit.
+pub fn truncate(value: &Value, max_size: usize) -> Option<String> {
+    STEPS.iter().find_map(|limits| {
+        let text = serde_json::to_string(&shrink(value, limits, None)).ok()?;
+        (text.len() <= max_size).then_some(text)
+    })
+}
+
+fn shrink(value: &Value, limits: &Limits, key: Option<&str>) -> Value {
+    match value {
+        Value::String(s) => Value::String(shrink_string(s, limits.string_chars)),
+        Value::Array(items) if key == Some("messages") => {
+            Value::Array(keep_recent(items, limits))
+        }
+        Value::Array(items) => Value::Array(keep_ends(items, limits)),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), shrink(v, limits, Some(k))))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn shrink_string(s: &str, max_chars: usize) -> String {
+    let total = s.chars().count();
+    if total <= max_chars {
+        return s.to_string();
+    }
+    let kept: String = s.chars().take(max_chars).collect();
+    format!("{}...[{} chars omitted]", kept, total - max_chars)
+}
+
+/// First and last items with a marker for the ones in between
+fn keep_ends(items: &[Value], limits: &Limits) -> Vec<Value> {
+    let keep = limits.head_items + limits.tail_items;
+    if items.len() <= keep + 1 {
+        return items.iter().map(|v| shrink(v, limits, None)).collect();
+    }
+
+    let omitted = items.len() - keep;
+    items[..limits.head_items]
+        .iter()
+        .map(|v| shrink(v, limits, None))
+        .chain(std::iter::once(omitted_marker(omitted)))
+        .chain(
+            items[items.len() - limits.tail_items..]
+                .iter()
+                .map(|v| shrink(v, limits, None)),
+        )
+        .collect()
+}
+
+/// The most recent conversation turns, after a marker for the older ones
+fn keep_recent(items: &[Value], limits: &Limits) -> Vec<Value> {
+    if items.len() <= limits.messages {
+        return items.iter().map(|v| shrink(v, limits, None)).collect();
+    }
+
+    let omitted = items.len() - limits.messages;
+    std::iter::once(omitted_marker(omitted))
+        .chain(items[omitted..].iter().map(|v| shrink(v, limits, None)))
+        .collect()
+}
+
+fn omitted_marker(count: usize) -> Value {
+    Value::String(format!("[{} items omitted]", count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_small_document_unchanged() {
+        let value = json!({"model": "claude-sonnet-4", "max_tokens": 1024});
+        let text = truncate(&value, 1024).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn test_long_strings_elided() {
+        let value = json!({"system": "x".repeat(10_000)});
+        let text = truncate(&value, 2000).unwrap();
+        assert!(text.len() <= 2000);
+
+        let parsed: Value = serde_json::from_str(&text).unwrap();
+        let system = parsed["system"].as_str().unwrap();
+        assert!(system.starts_with("xxxx"));
+        assert!(system.ends_with("chars omitted]"));
+    }
+
+    #[test]
+    fn test_strings_cut_on_char_boundaries() {
+        let value = json!({"text": "é".repeat(5000)});
+        let text = truncate(&value, 1500).unwrap();
+        let parsed: Value = serde_json::from_str(&text).unwrap();
+        assert!(parsed["text"].as_str().unwrap().starts_with("éé"));
+    }
+
+    #[test]
+    fn test_long_arrays_keep_head_and_tail() {
+        let value = json!({"tools": (0..500).collect::<Vec<_>>()});
+        let text = truncate(&value, 400).unwrap();
+        let parsed: Value = serde_json::from_str(&text).unwrap();
+
+        let tools = parsed["tools"].as_array().unwrap();
+        assert_eq!(tools[0], 0);
+        assert!(tools.iter().any(|v| v.as_str().is_some_and(|s| s.ends_with("items omitted]"))));
+        assert_eq!(tools.last().unwrap(), 499);
+    }
+
+    #[test]
+    fn test_messages_keep_recent_turns() {
+        let messages: Vec<Value> = (0..100)
+            .map(|i| json!({"role": if i % 2 == 0 { "user" } else { "assistant" }, "content": format!("turn {} {}", i, "lorem ipsum ".repeat(50))}))
+            .collect();
+        let value = json!({"model": "claude-sonnet-4", "messages": messages});
+
+        let text = truncate(&value, 8000).unwrap();
+        assert!(text.len() <= 8000);
+
+        let parsed: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed["model"], "claude-sonnet-4");
+        let kept = parsed["messages"].as_array().unwrap();
+        assert!(kept[0].as_str().unwrap().ends_with("items omitted]"));
+        let last = kept.last().unwrap()["content"].as_str().unwrap();
+        assert!(last.starts_with("turn 99"));
+    }
+
+    #[test]
+    fn test_gives_up_when_nothing_fits() {
+        let value = json!({"a": "b".repeat(100)});
+        assert!(truncate(&value, 5).is_none());
+    }
+}
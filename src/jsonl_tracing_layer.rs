@@ -1,8 +1,14 @@
 //! Custom tracing layer that writes all log events to JSONL files
+//!
+//! Events are recorded under the proxy run id. Events emitted inside a
+//! `connection` span (fields `connection_id` and `peer_addr`) also carry that
+//! client connection in their origin.
 
 use crate::log_writer::LogWriter;
-use crate::schema::LogEntry;
+use crate::schema::{LogEntry, Origin};
+use tracing::span::{Attributes, Id};
 use tracing::{Event, Subscriber};
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::Layer;
 use uuid::Uuid;
 
@@ -10,14 +16,23 @@ use uuid::Uuid;
 pub struct JsonlTracingLayer {
     log_writer: LogWriter,
     session_id: String,
+    origin: Origin,
+}
+
+/// Connection fields of a `connection` span, kept in its extensions
+#[derive(Default)]
+struct ConnectionFields {
+    connection_id: Option<u64>,
+    peer_addr: Option<String>,
 }
 
 impl JsonlTracingLayer {
-    /// Create a new JSONL tracing layer
-    pub fn new(log_writer: LogWriter) -> Self {
+    /// Create a new JSONL tracing layer for one proxy run
+    pub fn new(log_writer: LogWriter, proxy_run_id: Uuid) -> Self {
         Self {
             log_writer,
-            session_id: Uuid::new_v4().to_string(),
+            session_id: proxy_run_id.to_string(),
+            origin: Origin::run(proxy_run_id),
         }
     }
 
@@ -31,9 +46,20 @@ impl JsonlTracingLayer {
 
 impl<S> Layer<S> for JsonlTracingLayer
 where
-    S: Subscriber,
+    S: Subscriber + for<'a> LookupSpan<'a>,
 {
-    fn on_event(&self, event: &Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        if attrs.metadata().name() != "connection" {
+            return;
+        }
+        let mut fields = ConnectionFields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
         let metadata = event.metadata();
 
         let level = metadata.level().to_string().to_uppercase();
@@ -54,6 +80,18 @@ where
             .map(String::from)
             .or_else(|| module.and_then(|m| m.split("::").last()).map(String::from));
 
+        // Attribute the event to the innermost enclosing connection, if any
+        let mut origin = self.origin.clone();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope {
+                if let Some(fields) = span.extensions().get::<ConnectionFields>() {
+                    origin.connection_id = fields.connection_id;
+                    origin.peer_addr = fields.peer_addr.clone();
+                    break;
+                }
+            }
+        }
+
         let entry = LogEntry::new_proxy_debug(
             self.session_id.clone(),
             level,
@@ -62,12 +100,27 @@ where
             Some(target.to_string()),
             file.map(String::from),
             line,
-        );
+        )
+        .with_origin(&origin);
 
         self.write_log(entry);
     }
 }
 
+impl tracing::field::Visit for ConnectionFields {
+    fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+        if field.name() == "connection_id" {
+            self.connection_id = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "peer_addr" {
+            self.peer_addr = Some(format!("{:?}", value));
+        }
+    }
+}
+
 /// A visitor for extracting the message from tracing events
 struct MessageVisitor<'a>(&'a mut String);
 
@@ -115,4 +168,39 @@ impl<'a> tracing::field::Visit for MessageVisitor<'a> {
         }
         self.0.push_str(&format!("{} = {}", field.name(), value));
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::LogEvent;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_events_carry_run_and_connection() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let log_writer = LogWriter::new(temp_dir.path().to_path_buf()).unwrap();
+        let run_id = Uuid::new_v4();
+        let subscriber =
+            tracing_subscriber::registry().with(JsonlTracingLayer::new(log_writer.clone(), run_id));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("outside");
+            let peer: std::net::SocketAddr = "127.0.0.1:50000".parse().unwrap();
+            tracing::info_span!("connection", connection_id = 7u64, peer_addr = %peer).in_scope(|| {
+                tracing::info_span!("request").in_scope(|| tracing::info!("inside"));
+            });
+        });
+
+        let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        let entries = crate::tail_reader::read_all_entries(&log_writer.get_log_file_path(&date)).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let origins: Vec<_> = entries.iter().map(|e| e.origin.clone().unwrap()).collect();
+        assert!(origins.iter().all(|o| o.proxy_run_id == run_id && o.proxy_pid == std::process::id()));
+        assert_eq!(entries[0].session_id, run_id.to_string());
+        assert_eq!(origins[0].connection_id, None);
+        assert_eq!(origins[1].connection_id, Some(7));
+        assert_eq!(origins[1].peer_addr.as_deref(), Some("127.0.0.1:50000"));
+        assert!(matches!(&entries[1].event, LogEvent::ProxyDebug(d) if d.message == "inside"));
+    }
+}
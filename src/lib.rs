@@ -3,9 +3,17 @@
 //! This module exposes the core components needed for benchmarking
 //! and external usage.
 
+pub mod api_request;
+pub mod blob_store;
+pub mod json_truncate;
 pub mod log_writer;
+pub mod migration;
+pub mod rate_limit;
 pub mod schema;
+pub mod sse;
 pub mod tail_reader;
+pub mod usage;
+pub mod validate;
 
 // Re-export commonly used types
 pub use log_writer::LogWriter;
@@ -35,7 +35,7 @@ impl LogWriter {
                 let home = std::env::var("HOME")
                     .ok()
                     .map(PathBuf::from)
-                    .or_else(|| dirs::home_dir())
+                    .or_else(dirs::home_dir)
                     .ok_or_else(|| io::Error::new(
                         io::ErrorKind::NotFound,
                         "Could not determine home directory"
@@ -77,7 +77,7 @@ impl LogWriter {
 
         // Serialize directly to writer
         serde_json::to_writer(&mut writer, entry)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            .map_err(io::Error::other)?;
 
         // Write newline
         writer.write_all(b"\n")?;
@@ -101,7 +101,7 @@ impl LogWriter {
         // Run synchronous I/O in blocking thread pool
         tokio::task::spawn_blocking(move || writer.write_sync(&entry))
             .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+            .map_err(io::Error::other)?
     }
 
     /// Get the logs directory
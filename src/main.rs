@@ -35,14 +35,30 @@
 //!
 //! All modes write logs to the same unified daily log file.
 
+mod access;
+mod api_request;
+mod blob_store;
+mod capture;
 mod certificate_manager;
 mod claude_config;
+mod delta;
+mod intercept;
+mod json_truncate;
 mod jsonl_tracing_layer;
 mod log_writer;
+mod migration;
 mod proxy_config;
 mod proxy_server;
+mod rate_limit;
+mod retry;
 pub mod schema;
+mod session;
+mod shutdown;
+mod sse;
 mod tail_reader;
+mod upstream;
+mod usage;
+mod validate;
 
 use anyhow::{Context, Result};
 use certificate_manager::CertificateManager;
@@ -92,6 +108,43 @@ enum Commands {
         #[arg(short, long)]
         port: Option<u16>,
     },
+    /// Show token usage and cost totals for a day of proxy traffic
+    Usage {
+        /// Date to summarize (YYYY-MM-DD format), defaults to today
+        #[arg(short, long)]
+        date: Option<String>,
+    },
+    /// Show how close a day of proxy traffic came to the API rate limits
+    RateLimits {
+        /// Date to summarize (YYYY-MM-DD format), defaults to today
+        #[arg(short, long)]
+        date: Option<String>,
+    },
+    /// Rewrite log files written with older schema versions to the current one
+    Migrate {
+        /// Only migrate this date (YYYY-MM-DD format), defaults to every log file
+        #[arg(short, long)]
+        date: Option<String>,
+        /// Report what would change without rewriting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the JSON Schema of a log entry
+    Schema,
+    /// Check a daily log file against the JSON Schema, line by line
+    Validate {
+        /// Date to validate (YYYY-MM-DD format), defaults to today
+        #[arg(short, long)]
+        date: Option<String>,
+    },
+    /// Print the full recorded body of a proxied request, e.g. to replay it with curl
+    RequestBody {
+        /// Request id of the ProxyRequest entry
+        id: Uuid,
+        /// Date of the log file holding the request (YYYY-MM-DD format), defaults to today
+        #[arg(short, long)]
+        date: Option<String>,
+    },
     /// Initialize certificates and configuration
     Init {
         /// Force regenerate even if certificates exist
@@ -157,6 +210,18 @@ pub struct ClearLogRequest {
     pub date: String,
 }
 
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct UsageSummaryRequest {
+    /// Date to summarize (YYYY-MM-DD format), defaults to today
+    pub date: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct RateLimitSummaryRequest {
+    /// Date to summarize (YYYY-MM-DD format), defaults to today
+    pub date: Option<String>,
+}
+
 #[derive(Clone)]
 pub struct LocalLogger {
     log_writer: LogWriter,
@@ -182,8 +247,7 @@ impl LocalLogger {
 
     /// Validate date format (YYYY-MM-DD)
     fn validate_date_format(&self, date: &str) -> Result<(), ErrorData> {
-        if date.len() != 10 || !date.chars().nth(4).map_or(false, |c| c == '-') 
-            || !date.chars().nth(7).map_or(false, |c| c == '-') {
+        if !is_valid_date(date) {
             return Err(ErrorData::new(
                 ErrorCode::INVALID_PARAMS,
                 "Invalid date format. Expected YYYY-MM-DD".to_string(),
@@ -235,11 +299,9 @@ impl LocalLogger {
     ) -> Result<CallToolResult, ErrorData> {
         use schema::LogEvent;
 
-        let date = date.unwrap_or_else(|| chrono::Utc::now().format("%Y-%m-%d").to_string());
+        let date = date.unwrap_or_else(today);
 
-        if let Err(e) = self.validate_date_format(&date) {
-            return Err(e);
-        }
+        self.validate_date_format(&date)?;
 
         let log_file_path = self.get_log_file_path_for_date(&date);
 
@@ -293,7 +355,20 @@ impl LocalLogger {
                                     },
                                     BodyContent::Binary { .. } => format!("\n  Body: [Binary, {} bytes]", req.body.size_bytes),
                                     BodyContent::Truncated { preview, .. } => format!("\n  Body: {}... [truncated]", preview),
+                                    BodyContent::TruncatedJson { data, .. } => format!("\n  Body: {} [truncated]", data),
                                     BodyContent::DecompressionFailed { error } => format!("\n  Body: [Decompression failed: {}]", error),
+                                    BodyContent::EventStream { message, .. } => format!(
+                                        "\n  Body: [Event stream: {} content block(s), stop reason: {}]",
+                                        message.content.len(),
+                                        message.stop_reason.as_deref().unwrap_or("none")
+                                    ),
+                                    BodyContent::Omitted => format!("\n  Body: [Not recorded, {} bytes]", req.body.size_bytes),
+                                    BodyContent::BlobRef { hash } => format!("\n  Body: [Blob {} missing, {} bytes]", hash, req.body.stored_size_bytes),
+                                    BodyContent::Delta { parent_request_id, messages, .. } => format!(
+                                        "\n  Body: [Extends request {} with {} message(s)]",
+                                        parent_request_id,
+                                        messages.len()
+                                    ),
                                     BodyContent::Empty => String::new(),
                                 };
                                 format!(
@@ -319,18 +394,47 @@ impl LocalLogger {
                                     },
                                     BodyContent::Binary { .. } => format!("\n  Body: [Binary, {} bytes]", resp.body.size_bytes),
                                     BodyContent::Truncated { preview, .. } => format!("\n  Body: {}... [truncated]", preview),
+                                    BodyContent::TruncatedJson { data, .. } => format!("\n  Body: {} [truncated]", data),
                                     BodyContent::DecompressionFailed { error } => format!("\n  Body: [Decompression failed: {}]", error),
+                                    BodyContent::EventStream { message, .. } => format!(
+                                        "\n  Body: [Event stream: {} content block(s), stop reason: {}]",
+                                        message.content.len(),
+                                        message.stop_reason.as_deref().unwrap_or("none")
+                                    ),
+                                    BodyContent::Omitted => format!("\n  Body: [Not recorded, {} bytes]", resp.body.size_bytes),
+                                    BodyContent::BlobRef { hash } => format!("\n  Body: [Blob {} missing, {} bytes]", hash, resp.body.stored_size_bytes),
+                                    BodyContent::Delta { parent_request_id, messages, .. } => format!(
+                                        "\n  Body: [Extends request {} with {} message(s)]",
+                                        parent_request_id,
+                                        messages.len()
+                                    ),
                                     BodyContent::Empty => String::new(),
                                 };
+                                // Exchanges cut short have no status, only the reason
+                                let outcome = match &resp.aborted {
+                                    Some(reason) => format!("Aborted: {}", reason),
+                                    None => format!("Status: {}", resp.status),
+                                };
                                 format!(
-                                    "[{}] [PROXY:RESPONSE] Status: {} Duration: {}ms (Req ID: {}){}",
+                                    "[{}] [PROXY:RESPONSE] {} Duration: {}ms{} (Req ID: {}){}{}",
                                     entry.timestamp.format("%H:%M:%S"),
-                                    resp.status,
+                                    outcome,
                                     resp.duration_ms,
+                                    resp.ttfb_ms.map(|t| format!(" TTFB: {}ms", t)).unwrap_or_default(),
                                     resp.request_id,
+                                    resp.upstream_request_id.as_ref().map(|id| format!(" (Upstream ID: {})", id)).unwrap_or_default(),
                                     body_preview
                                 )
                             },
+                            LogEvent::ProxyError(err) => format!(
+                                "[{}] [PROXY:ERROR] {} ({}) Status: {} Duration: {}ms (Req ID: {})",
+                                entry.timestamp.format("%H:%M:%S"),
+                                err.kind,
+                                err.message,
+                                err.status,
+                                err.duration_ms,
+                                err.request_id
+                            ),
                             LogEvent::ProxyDebug(debug) => {
                                 format!(
                                     "[{}] [{}] [{}] {}{}",
@@ -341,6 +445,17 @@ impl LocalLogger {
                                     debug.line.map(|l| format!(" (line {})", l)).unwrap_or_default()
                                 )
                             },
+                            LogEvent::Unknown(unknown) => {
+                                let fields = serde_json::to_string(&unknown.payload).unwrap_or_default();
+                                let preview: String = fields.chars().take(500).collect();
+                                format!(
+                                    "[{}] [{}] {}{}",
+                                    entry.timestamp.format("%H:%M:%S"),
+                                    unknown.kind,
+                                    preview,
+                                    if preview.len() < fields.len() { "..." } else { "" }
+                                )
+                            },
                         }
                     })
                     .collect();
@@ -360,31 +475,87 @@ impl LocalLogger {
         }
     }
 
+    #[tool(description = "Summarize Claude API token usage and cost for a day, in total and per session and model")]
+    async fn usage_summary(
+        &self,
+        Parameters(UsageSummaryRequest { date }): Parameters<UsageSummaryRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let date = date.unwrap_or_else(today);
+
+        self.validate_date_format(&date)?;
+
+        match load_day(&self.log_writer, &date) {
+            Ok(Some((_, entries))) => {
+                let summary = usage::summarize(&entries);
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "Usage for {}:\n\n{}",
+                    date, summary
+                ))]))
+            }
+            Ok(None) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "No logs found for date: {}",
+                date
+            ))])),
+            Err(e) => Err(ErrorData::new(
+                ErrorCode::INTERNAL_ERROR,
+                format!("Failed to read log file: {:#}", e),
+                None,
+            )),
+        }
+    }
+
+    #[tool(description = "Show how close a day of Claude API traffic came to its rate limits, with the upstream request ids of throttled responses")]
+    async fn rate_limit_summary(
+        &self,
+        Parameters(RateLimitSummaryRequest { date }): Parameters<RateLimitSummaryRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let date = date.unwrap_or_else(today);
+
+        self.validate_date_format(&date)?;
+
+        match load_day(&self.log_writer, &date) {
+            Ok(Some((_, entries))) => {
+                let summary = rate_limit::summarize(&entries);
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "Rate limits for {}:\n\n{}",
+                    date, summary
+                ))]))
+            }
+            Ok(None) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "No logs found for date: {}",
+                date
+            ))])),
+            Err(e) => Err(ErrorData::new(
+                ErrorCode::INTERNAL_ERROR,
+                format!("Failed to read log file: {:#}", e),
+                None,
+            )),
+        }
+    }
+
     #[tool(description = "List all available daily log files")]
     async fn list_log_files(&self) -> Result<CallToolResult, ErrorData> {
         match fs::read_dir(self.log_writer.logs_dir()) {
             Ok(entries) => {
                 let mut log_files = Vec::new();
 
-                for entry in entries {
-                    if let Ok(entry) = entry {
-                        let path = entry.path();
-                        if path.is_file() && path.extension().map_or(false, |ext| ext == "jsonl") {
-                            if let Some(filename) = path.file_stem().and_then(|n| n.to_str()) {
-                                // Validate that it's a date format
-                                if filename.len() == 10 && filename.chars().nth(4) == Some('-') 
-                                    && filename.chars().nth(7) == Some('-') {
-                                    let metadata = fs::metadata(&path).ok();
-                                    let size = metadata.as_ref().map(|m| m.len()).unwrap_or(0);
-                                    
-                                    // Count number of entries in the file
-                                    let entry_count = File::open(&path)
-                                        .ok()
-                                        .map(|f| BufReader::new(f).lines().count())
-                                        .unwrap_or(0);
-
-                                    log_files.push((filename.to_string(), size, entry_count));
-                                }
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    if path.is_file() && path.extension().is_some_and(|ext| ext == "jsonl") {
+                        if let Some(filename) = path.file_stem().and_then(|n| n.to_str()) {
+                            // Validate that it's a date format
+                            if filename.len() == 10 && filename.chars().nth(4) == Some('-') 
+                                && filename.chars().nth(7) == Some('-') {
+                                let metadata = fs::metadata(&path).ok();
+                                let size = metadata.as_ref().map(|m| m.len()).unwrap_or(0);
+                                
+                                // Count number of entries in the file
+                                let entry_count = File::open(&path)
+                                    .ok()
+                                    .map(|f| BufReader::new(f).lines().count())
+                                    .unwrap_or(0);
+
+                                log_files.push((filename.to_string(), size, entry_count));
                             }
                         }
                     }
@@ -425,9 +596,7 @@ impl LocalLogger {
         &self,
         Parameters(ClearLogRequest { date }): Parameters<ClearLogRequest>,
     ) -> Result<CallToolResult, ErrorData> {
-        if let Err(e) = self.validate_date_format(&date) {
-            return Err(e);
-        }
+        self.validate_date_format(&date)?;
 
         let log_file_path = self.get_log_file_path_for_date(&date);
 
@@ -464,7 +633,8 @@ impl ServerHandler for LocalLogger {
             server_info: Implementation::from_build_env(),
             instructions: Some(concat!(
                 "This is a local logging MCP server that provides tools for managing log files. ",
-                "You can write log messages, read recent entries, list available log files, and clear log files. ",
+                "You can write log messages, read recent entries, list available log files, clear log files, ",
+                "and summarize Claude API token usage and cost. ",
                 "All log files are stored in a 'logs' directory relative to the server's working directory. ",
                 "Log entries include timestamps and severity levels for better organization."
             ).to_string()),
@@ -487,6 +657,30 @@ fn main() -> Result<()> {
                 .build()?
                 .block_on(run_proxy_server(config, port))
         }
+        Some(Commands::Usage { date }) => {
+            // Summarize usage synchronously
+            run_usage_command(date)
+        }
+        Some(Commands::RateLimits { date }) => {
+            // Summarize rate limits synchronously
+            run_rate_limits_command(date)
+        }
+        Some(Commands::Migrate { date, dry_run }) => {
+            // Migrate log files synchronously
+            run_migrate_command(date, dry_run)
+        }
+        Some(Commands::Schema) => {
+            println!("{}", serde_json::to_string_pretty(&schema::json_schema())?);
+            Ok(())
+        }
+        Some(Commands::Validate { date }) => {
+            // Validate synchronously
+            run_validate_command(date)
+        }
+        Some(Commands::RequestBody { id, date }) => {
+            // Rebuild the body synchronously
+            run_request_body_command(id, date)
+        }
         Some(Commands::Init { force, cert_dir, quiet }) => {
             // Run certificate initialization synchronously
             run_init_command(force, cert_dir, quiet)
@@ -547,8 +741,11 @@ async fn run_proxy_server(config_path: Option<PathBuf>, port: Option<u16>) -> Re
             .map_err(|e| anyhow::anyhow!("Failed to create LogWriter: {}", e))?
     );
 
+    // One id for everything this proxy process records
+    let proxy_run_id = Uuid::new_v4();
+
     // Initialize custom tracing with JSONL output using unified LogWriter
-    let jsonl_layer = JsonlTracingLayer::new(log_writer.as_ref().clone());
+    let jsonl_layer = JsonlTracingLayer::new(log_writer.as_ref().clone(), proxy_run_id);
 
     tracing_subscriber::registry()
         .with(EnvFilter::from_default_env().add_directive(tracing::Level::INFO.into()))
@@ -568,7 +765,7 @@ async fn run_proxy_server(config_path: Option<PathBuf>, port: Option<u16>) -> Re
     }
 
     // Create and run proxy server with unified LogWriter
-    let proxy = ProxyServer::new(config, log_writer)?;
+    let proxy = ProxyServer::new(config, log_writer, proxy_run_id)?;
     proxy.run().await?;
 
     Ok(())
@@ -640,6 +837,177 @@ fn run_hook_mode_sync() -> Result<()> {
     Ok(())
 }
 
+/// Whether `date` is a YYYY-MM-DD date, and so safe to name a log file after
+fn is_valid_date(date: &str) -> bool {
+    date.len() == 10 && chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok()
+}
+
+/// Reject a `--date` argument that is not YYYY-MM-DD
+fn validate_date_arg(date: &str) -> Result<()> {
+    if !is_valid_date(date) {
+        anyhow::bail!("Invalid date format: {}. Expected YYYY-MM-DD", date);
+    }
+    Ok(())
+}
+
+/// Today's date, the default for commands and tools taking a date
+fn today() -> String {
+    chrono::Utc::now().format("%Y-%m-%d").to_string()
+}
+
+/// The log file for `date`, or None when nothing was logged that day
+fn day_log_path(log_writer: &LogWriter, date: &str) -> Result<Option<PathBuf>> {
+    validate_date_arg(date)?;
+    let log_file_path = log_writer.get_log_file_path(date);
+    Ok(log_file_path.exists().then_some(log_file_path))
+}
+
+/// Every entry logged on `date`, or None when nothing was logged that day
+///
+/// Bodies are left as recorded; the daily reports only use the other fields.
+fn load_day(log_writer: &LogWriter, date: &str) -> Result<Option<(PathBuf, Vec<LogEntry>)>> {
+    let Some(log_file_path) = day_log_path(log_writer, date)? else {
+        return Ok(None);
+    };
+    let entries = tail_reader::read_all_entries_as_recorded(&log_file_path)
+        .with_context(|| format!("Failed to read {}", log_file_path.display()))?;
+    Ok(Some((log_file_path, entries)))
+}
+
+/// Print token usage and cost totals for one day
+fn run_usage_command(date: Option<String>) -> Result<()> {
+    let date = date.unwrap_or_else(today);
+    let log_writer = LogWriter::from_env()
+        .map_err(|e| anyhow::anyhow!("Failed to create LogWriter: {}", e))?;
+
+    let Some((_, entries)) = load_day(&log_writer, &date)? else {
+        println!("No logs found for date: {}", date);
+        return Ok(());
+    };
+
+    println!("Usage for {}:\n", date);
+    print!("{}", usage::summarize(&entries));
+
+    Ok(())
+}
+
+fn run_rate_limits_command(date: Option<String>) -> Result<()> {
+    let date = date.unwrap_or_else(today);
+    let log_writer = LogWriter::from_env()
+        .map_err(|e| anyhow::anyhow!("Failed to create LogWriter: {}", e))?;
+
+    let Some((_, entries)) = load_day(&log_writer, &date)? else {
+        println!("No logs found for date: {}", date);
+        return Ok(());
+    };
+
+    println!("Rate limits for {}:\n", date);
+    print!("{}", rate_limit::summarize(&entries));
+
+    Ok(())
+}
+
+/// Report the lines of a daily log file that do not conform to the schema
+fn run_validate_command(date: Option<String>) -> Result<()> {
+    let date = date.unwrap_or_else(today);
+    let log_writer = LogWriter::from_env()
+        .map_err(|e| anyhow::anyhow!("Failed to create LogWriter: {}", e))?;
+
+    let Some(log_file_path) = day_log_path(&log_writer, &date)? else {
+        println!("No logs found for date: {}", date);
+        return Ok(());
+    };
+
+    let report = validate::validate_file(&log_file_path)
+        .with_context(|| format!("Failed to validate {}", log_file_path.display()))?;
+    print!("{}", report);
+
+    if !report.is_valid() {
+        anyhow::bail!("{} line(s) do not conform to the schema", report.invalid.len());
+    }
+    Ok(())
+}
+
+/// Write a request body to stdout, rebuilding delta-encoded and blob-stored bodies
+fn run_request_body_command(id: Uuid, date: Option<String>) -> Result<()> {
+    use std::io::Write;
+
+    let date = date.unwrap_or_else(today);
+    let log_writer = LogWriter::from_env()
+        .map_err(|e| anyhow::anyhow!("Failed to create LogWriter: {}", e))?;
+
+    let Some(log_file_path) = day_log_path(&log_writer, &date)? else {
+        anyhow::bail!("No logs found for date: {}", date);
+    };
+
+    let bytes = read_request_body(&log_file_path, id)?;
+    std::io::stdout().write_all(&bytes)?;
+    Ok(())
+}
+
+/// Full body of the request with the given id in a daily log file
+fn read_request_body(log_file_path: &PathBuf, id: Uuid) -> Result<Vec<u8>> {
+    use base64::Engine;
+    use schema::{BodyContent, LogEvent};
+
+    let entries = tail_reader::read_all_entries(log_file_path)
+        .with_context(|| format!("Failed to read {}", log_file_path.display()))?;
+    let body = entries
+        .into_iter()
+        .find_map(|entry| match entry.event {
+            LogEvent::ProxyRequest(req) if req.id == id => Some(req.body),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("No request {} in {}", id, log_file_path.display()))?;
+
+    match body.content {
+        BodyContent::Text { data } => Ok(data.into_bytes()),
+        BodyContent::Binary { data } => base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .context("Recorded binary body is not valid base64"),
+        _ => anyhow::bail!("The body of request {} was not recorded in full", id),
+    }
+}
+
+/// Upgrade old entries in one or all daily log files
+fn run_migrate_command(date: Option<String>, dry_run: bool) -> Result<()> {
+    let log_writer = LogWriter::from_env()
+        .map_err(|e| anyhow::anyhow!("Failed to create LogWriter: {}", e))?;
+
+    let paths = match date {
+        Some(date) => {
+            validate_date_arg(&date)?;
+            let path = log_writer.get_log_file_path(&date);
+            if !path.exists() {
+                println!("No logs found for date: {}", date);
+                return Ok(());
+            }
+            vec![path]
+        }
+        None => {
+            let mut paths: Vec<PathBuf> = std::fs::read_dir(log_writer.logs_dir())
+                .with_context(|| format!("Failed to list {}", log_writer.logs_dir().display()))?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+                .collect();
+            paths.sort();
+            paths
+        }
+    };
+
+    for path in paths {
+        let report = migration::migrate_file(&path, dry_run)
+            .with_context(|| format!("Failed to migrate {}", path.display()))?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        println!("{}: {}", name, report);
+    }
+    if dry_run {
+        println!("\nDry run, no files were changed");
+    }
+
+    Ok(())
+}
+
 /// Initialize certificates synchronously
 ///
 /// This function:
@@ -766,6 +1134,61 @@ mod tests {
         assert!(logger.validate_date_format("2025/01/19").is_err());
         assert!(logger.validate_date_format("25-01-19").is_err());
         assert!(logger.validate_date_format("not-a-date").is_err());
+        assert!(logger.validate_date_format("../a-b-cdef").is_err());
+        assert!(logger.validate_date_format("2025-13-01").is_err());
+    }
+
+    #[test]
+    fn test_cli_date_arguments_validated() {
+        assert!(validate_date_arg("2025-01-19").is_ok());
+        assert!(validate_date_arg("../../etc/x").is_err());
+
+        // Rejected before any log file is looked up
+        for result in [
+            run_usage_command(Some("2025/01/19".to_string())),
+            run_rate_limits_command(Some("yesterday".to_string())),
+            run_migrate_command(Some("2025-1-19".to_string()), true),
+            run_validate_command(Some("19-01-2025".to_string())),
+            run_request_body_command(Uuid::new_v4(), Some("../x-y-2025".to_string())),
+        ] {
+            let err = result.unwrap_err().to_string();
+            assert!(err.contains("Expected YYYY-MM-DD"), "{}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_day() {
+        let logger = create_test_logger().unwrap();
+        assert!(load_day(&logger.log_writer, "2001-01-01").unwrap().is_none());
+        assert!(load_day(&logger.log_writer, "../2001-01-01").is_err());
+
+        let entry = LogEntry::new_mcp("session".to_string(), "INFO".to_string(), "hello".to_string());
+        logger.write_log_entry(entry).await.unwrap();
+
+        let (path, entries) = load_day(&logger.log_writer, &today()).unwrap().unwrap();
+        assert_eq!(path, logger.get_log_file_path_for_date(&today()));
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_read_logs_shows_abort_reason() {
+        let logger = create_test_logger().unwrap();
+        let entry = LogEntry::new_proxy_aborted(
+            "session".to_string(),
+            "correlation".to_string(),
+            Uuid::new_v4(),
+            1500,
+            "proxy shut down before the exchange finished".to_string(),
+        );
+        logger.log_writer.write_sync(&entry).unwrap();
+
+        let result = logger
+            .read_logs(Parameters(ReadLogsRequest { date: None, lines: Some(10) }))
+            .await
+            .unwrap();
+        let text = &result.content[0].as_text().unwrap().text;
+        assert!(text.contains("[PROXY:RESPONSE] Aborted: proxy shut down before the exchange finished Duration: 1500ms"));
+        assert!(!text.contains("Status: 0"));
     }
 
     #[test]
@@ -787,7 +1210,7 @@ mod tests {
         assert!(json.contains("\"type\":\"Mcp\""));
         assert!(json.contains("\"level\":\"INFO\""));
         assert!(json.contains("\"message\":\"Test message\""));
-        assert!(json.contains("\"schema_version\":1"));
+        assert!(json.contains(&format!("\"schema_version\":{}", schema::SCHEMA_VERSION)));
     }
 
     #[test]
@@ -821,4 +1244,46 @@ mod tests {
         assert_eq!(hook_event.tool_name, None);
         assert_eq!(hook_event.tool_input, None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_read_request_body_rebuilds_delta() {
+        use schema::{BodyContent, BodyData, Headers, LogEvent, ProxyRequestEvent};
+
+        let temp_dir = TempDir::new().unwrap();
+        let writer = LogWriter::new(temp_dir.path().to_path_buf()).unwrap();
+        let deltas = delta::DeltaEncoder::default();
+        let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
+
+        let first = r#"{"model":"claude-sonnet-4-5","messages":[{"role":"user","content":"hi"}]}"#;
+        let second = r#"{"model":"claude-sonnet-4-5","messages":[{"role":"user","content":"hi"},{"role":"assistant","content":"hello"}]}"#;
+        let mut ids = Vec::new();
+        for body in [first, second] {
+            let id = Uuid::new_v4();
+            let (body_data, pending) = deltas.encode(id, "session", &date, BodyData::from_bytes(body.as_bytes(), None, None, usize::MAX));
+            let entry = LogEntry::new_proxy_request(
+                "session".to_string(),
+                "correlation".to_string(),
+                ProxyRequestEvent::new(
+                    id,
+                    "POST".to_string(),
+                    "https://api.anthropic.com/v1/messages".to_string(),
+                    Headers::default(),
+                    body_data,
+                ),
+            );
+            writer.write_sync(&entry).unwrap();
+            deltas.remember(pending.unwrap());
+            ids.push((id, entry));
+        }
+        let LogEvent::ProxyRequest(req) = &ids[1].1.event else {
+            panic!("Expected ProxyRequest event");
+        };
+        assert!(matches!(req.body.content, BodyContent::Delta { .. }));
+
+        let path = writer.get_log_file_path(&date);
+        let rebuilt: serde_json::Value = serde_json::from_slice(&read_request_body(&path, ids[1].0).unwrap()).unwrap();
+        assert_eq!(rebuilt, serde_json::from_str::<serde_json::Value>(second).unwrap());
+        assert_eq!(read_request_body(&path, ids[0].0).unwrap(), first.as_bytes());
+        assert!(read_request_body(&path, Uuid::new_v4()).is_err());
+    }
+}
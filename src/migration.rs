@@ -0,0 +1,364 @@
+//! Reading and upgrading entries written with older schema versions
+//!
+//! Every entry records the [`SCHEMA_VERSION`] it was written with. When the
+//! schema changes in a way older lines no longer deserialize into, the version
+//! is bumped and an upgrader from the previous version is appended to
+//! [`UPGRADERS`]. Upgraders work on the raw JSON, so they never need the old
+//! Rust types. [`LogEntry::parse_any_version`] applies them one after another
+//! when reading, and [`migrate_file`] rewrites a daily file to the current
+//! version on disk.
+
+use crate::schema::{LogEntry, SCHEMA_VERSION};
+use fs2::FileExt;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Rewrites an entry of version N in place into version N + 1
+type Upgrader = fn(&mut Map<String, Value>);
+
+/// One upgrader per version bump; the one at index N - 1 upgrades version N
+const UPGRADERS: &[Upgrader] = &[
+    // 1 -> 2: headers stored as a {name: value} object become [name, value] pairs
+    v1_headers_as_pairs,
+];
+
+const _: () = assert!(UPGRADERS.len() as u32 + 1 == SCHEMA_VERSION);
+
+/// Why a line could not be read as a [`LogEntry`]
+#[derive(Debug)]
+pub enum ParseError {
+    /// Not JSON, or not a log entry even after upgrading
+    Json(serde_json::Error),
+    /// Written by a newer local-logger than this one
+    NewerVersion(u32),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json(e) => write!(f, "invalid log entry: {}", e),
+            Self::NewerVersion(version) => write!(
+                f,
+                "schema version {} is newer than the supported version {}",
+                version, SCHEMA_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Json(e) => Some(e),
+            Self::NewerVersion(_) => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for ParseError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+#[derive(Deserialize)]
+struct VersionOnly {
+    schema_version: u32,
+}
+
+impl LogEntry {
+    /// Parse a log line written with any schema version
+    ///
+    /// Lines of the current version are deserialized directly; older ones are
+    /// upgraded first. Lines from a newer version are read as far as this
+    /// version understands them: unknown fields are ignored and unknown event
+    /// types become [`crate::schema::LogEvent::Unknown`].
+    pub fn parse_any_version(line: &str) -> Result<Self, ParseError> {
+        let VersionOnly { schema_version } = serde_json::from_str(line)?;
+        if schema_version >= SCHEMA_VERSION {
+            return Ok(serde_json::from_str(line)?);
+        }
+
+        let mut value = serde_json::from_str(line)?;
+        upgrade(&mut value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+/// Bring a raw entry up to [`SCHEMA_VERSION`]
+///
+/// Returns the version the entry had before.
+pub fn upgrade(value: &mut Value) -> Result<u32, ParseError> {
+    use serde::de::Error;
+
+    let Value::Object(entry) = value else {
+        return Err(ParseError::Json(serde_json::Error::custom("log entry is not a JSON object")));
+    };
+    let original = entry
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| ParseError::Json(serde_json::Error::missing_field("schema_version")))?
+        as u32;
+    if original > SCHEMA_VERSION {
+        return Err(ParseError::NewerVersion(original));
+    }
+
+    // Version numbers start at 1
+    let mut version = original.max(1);
+    while version < SCHEMA_VERSION {
+        UPGRADERS[version as usize - 1](entry);
+        version += 1;
+        entry.insert("schema_version".to_string(), Value::from(version));
+    }
+    Ok(original)
+}
+
+/// Outcome of migrating one log file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Entries already at the current version
+    pub current: usize,
+    /// Entries rewritten from an older version
+    pub upgraded: usize,
+    /// Entries from a newer version, left untouched
+    pub newer: usize,
+    /// Lines left untouched because they could not be read
+    pub unreadable: usize,
+}
+
+impl fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} upgraded, {} already current, {} newer, {} unreadable",
+            self.upgraded, self.current, self.newer, self.unreadable
+        )
+    }
+}
+
+/// Rewrite a log file so every readable entry is at [`SCHEMA_VERSION`]
+///
+/// Unreadable lines and lines from newer versions are kept byte for byte. The
+/// file is held under the writers' exclusive lock, and rewritten in place so
+/// writers waiting on the lock append to the migrated file. The new content is
+/// first saved next to it as `<file>.migrating`, which survives a crash in
+/// the middle of the rewrite. With `dry_run` nothing is written.
+pub fn migrate_file(path: &Path, dry_run: bool) -> io::Result<MigrationReport> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    file.lock_exclusive()?;
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+
+    let mut report = MigrationReport::default();
+    let mut migrated = Vec::with_capacity(data.len());
+    for line in data.split_inclusive(|b| *b == b'\n') {
+        let upgraded = match std::str::from_utf8(line) {
+            Ok(text) => upgrade_line(text.trim_end(), &mut report),
+            Err(_) => {
+                report.unreadable += 1;
+                None
+            }
+        };
+        match upgraded {
+            Some(upgraded) => {
+                migrated.extend_from_slice(upgraded.as_bytes());
+                migrated.push(b'\n');
+            }
+            None => migrated.extend_from_slice(line),
+        }
+    }
+
+    if report.upgraded == 0 || dry_run {
+        return Ok(report);
+    }
+
+    let mut backup_name = path.file_name().unwrap_or_default().to_os_string();
+    backup_name.push(".migrating");
+    let backup = path.with_file_name(backup_name);
+    fs::write(&backup, &migrated)?;
+    fs::File::open(&backup)?.sync_all()?;
+
+    file.seek(SeekFrom::Start(0))?;
+    file.set_len(0)?;
+    file.write_all(&migrated)?;
+    file.sync_all()?;
+    fs::remove_file(&backup)?;
+
+    Ok(report)
+}
+
+/// The upgraded form of an old line, or None to keep the line as it is
+fn upgrade_line(line: &str, report: &mut MigrationReport) -> Option<String> {
+    if line.is_empty() {
+        return None;
+    }
+    match serde_json::from_str::<VersionOnly>(line) {
+        Ok(VersionOnly { schema_version }) if schema_version == SCHEMA_VERSION => {
+            report.current += 1;
+            return None;
+        }
+        Ok(VersionOnly { schema_version }) if schema_version > SCHEMA_VERSION => {
+            report.newer += 1;
+            return None;
+        }
+        Ok(_) => {}
+        Err(_) => {
+            report.unreadable += 1;
+            return None;
+        }
+    }
+
+    let upgraded = LogEntry::parse_any_version(line)
+        .ok()
+        .and_then(|entry| serde_json::to_string(&entry).ok());
+    match &upgraded {
+        Some(_) => report.upgraded += 1,
+        None => report.unreadable += 1,
+    }
+    upgraded
+}
+
+fn v1_headers_as_pairs(entry: &mut Map<String, Value>) {
+    let Some(Value::Object(event)) = entry.get_mut("event") else {
+        return;
+    };
+    let Some(Value::Object(headers)) = event.get("headers") else {
+        return;
+    };
+
+    let mut pairs: Vec<(String, Value)> = headers.clone().into_iter().collect();
+    pairs.sort_by(|a, b| a.0.cmp(&b.0));
+    let pairs = pairs
+        .into_iter()
+        .map(|(name, value)| Value::Array(vec![Value::String(name), value]))
+        .collect();
+    event.insert("headers".to_string(), Value::Array(pairs));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::LogEvent;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn v1_request() -> Value {
+        json!({
+            "schema_version": 1,
+            "timestamp": "2025-01-01T12:00:00Z",
+            "date": "2025-01-01",
+            "session_id": "session",
+            "correlation_id": "correlation",
+            "event": {
+                "type": "ProxyRequest",
+                "id": "7f2c1b9e-4d3a-4c5b-9e8f-0a1b2c3d4e5f",
+                "method": "POST",
+                "uri": "https://api.anthropic.com/v1/messages",
+                "headers": {"x-api-key": "[REDACTED]", "content-type": "application/json"},
+                "body": {"size_bytes": 0, "stored_size_bytes": 0, "truncated": false, "content": {"type": "Empty"}}
+            }
+        })
+    }
+
+    #[test]
+    fn test_parse_v1_headers_map() {
+        let entry = LogEntry::parse_any_version(&v1_request().to_string()).unwrap();
+
+        assert_eq!(entry.schema_version, SCHEMA_VERSION);
+        let LogEvent::ProxyRequest(req) = &entry.event else {
+            panic!("Expected ProxyRequest event");
+        };
+        assert_eq!(req.headers.len(), 2);
+        assert_eq!(req.headers.iter().next().unwrap().0, "content-type");
+        assert_eq!(req.headers.get("Content-Type"), Some("application/json"));
+        assert_eq!(req.headers.get("X-Api-Key"), Some("[REDACTED]"));
+    }
+
+    fn newer_line(event: Value) -> String {
+        json!({
+            "schema_version": SCHEMA_VERSION + 1,
+            "timestamp": "2025-01-01T12:00:00Z",
+            "date": "2025-01-01",
+            "session_id": "session",
+            "correlation_id": "correlation",
+            "event": event
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_parse_newer_version_lines() {
+        let known = newer_line(json!({"type": "Mcp", "level": "INFO", "message": "hi", "added": true}));
+        let entry = LogEntry::parse_any_version(&known).unwrap();
+        assert_eq!(entry.schema_version, SCHEMA_VERSION + 1);
+        let LogEvent::Mcp(mcp) = &entry.event else {
+            panic!("Expected Mcp event, got {:?}", entry.event);
+        };
+        assert_eq!(mcp.message, "hi");
+
+        let unknown = newer_line(json!({"type": "ToolTrace", "tool": "Bash"}));
+        let entry = LogEntry::parse_any_version(&unknown).unwrap();
+        let LogEvent::Unknown(event) = &entry.event else {
+            panic!("Expected Unknown event, got {:?}", entry.event);
+        };
+        assert_eq!(event.kind, "ToolTrace");
+        assert_eq!(event.payload["tool"], "Bash");
+    }
+
+    #[test]
+    fn test_parse_current_and_rejected_lines() {
+        let entry = LogEntry::new_mcp("session".to_string(), "INFO".to_string(), "hi".to_string());
+        let line = serde_json::to_string(&entry).unwrap();
+        assert_eq!(LogEntry::parse_any_version(&line).unwrap().session_id, "session");
+
+        assert!(matches!(LogEntry::parse_any_version("not json"), Err(ParseError::Json(_))));
+        assert!(matches!(LogEntry::parse_any_version("{}"), Err(ParseError::Json(_))));
+    }
+
+    #[test]
+    fn test_migrate_file_in_place() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("2025-01-01.jsonl");
+        let current = serde_json::to_string(&LogEntry::new_mcp(
+            "session".to_string(),
+            "INFO".to_string(),
+            "hi".to_string(),
+        ))
+        .unwrap();
+        let newer = newer_line(json!({"type": "ToolTrace", "tool": "Bash"}));
+        let original = format!("{}\n{}\nnot json\n{}\n", v1_request(), current, newer);
+        fs::write(&path, &original).unwrap();
+
+        let report = migrate_file(&path, true).unwrap();
+        assert_eq!(
+            report,
+            MigrationReport {
+                current: 1,
+                upgraded: 1,
+                newer: 1,
+                unreadable: 1,
+            }
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+
+        migrate_file(&path, false).unwrap();
+        let migrated = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = migrated.lines().collect();
+        assert_eq!(lines.len(), 4);
+        let upgraded: LogEntry = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(upgraded.schema_version, SCHEMA_VERSION);
+        assert_eq!(lines[1], current);
+        assert_eq!(lines[2], "not json");
+        assert_eq!(lines[3], newer);
+        assert!(!temp_dir.path().join("2025-01-01.jsonl.migrating").exists());
+
+        let again = migrate_file(&path, false).unwrap();
+        assert_eq!(again.upgraded, 0);
+        assert_eq!(again.current, 2);
+    }
+}
@@ -1,7 +1,9 @@
 //! Configuration for the proxy server
 
+use crate::schema::MessageUsage;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 
@@ -13,6 +15,10 @@ pub struct ProxyConfig {
     #[serde(default = "default_listen_port")]
     pub listen_port: u16,
 
+    /// Seconds to wait for in-flight exchanges when shutting down
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+
     #[serde(default)]
     pub tls: TlsConfig,
 
@@ -21,6 +27,19 @@ pub struct ProxyConfig {
 
     #[serde(default)]
     pub filtering: FilteringConfig,
+
+    #[serde(default)]
+    pub access: AccessConfig,
+
+    #[serde(default)]
+    pub pricing: PricingConfig,
+
+    #[serde(default)]
+    pub upstream: UpstreamConfig,
+
+    /// Corporate HTTP proxy to chain outgoing connections through
+    #[serde(default)]
+    pub upstream_proxy: Option<UpstreamProxyConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,15 +64,168 @@ pub struct RecordingConfig {
 
     #[serde(default = "default_max_body_size")]
     pub max_body_size: usize,
+
+    /// Keep the raw event list alongside reassembled event-stream messages
+    #[serde(default)]
+    pub keep_sse_events: bool,
+
+    /// Store bodies larger than this many bytes once in the `blobs/`
+    /// directory and reference them by hash (unset keeps every body inline)
+    #[serde(default)]
+    pub blob_threshold_bytes: Option<usize>,
+
+    /// Record Claude request bodies as the messages added since the previous
+    /// turn of the same conversation
+    #[serde(default)]
+    pub delta_encode_requests: bool,
+
+    /// Keep complete tool definitions, not just names, in the typed
+    /// summary of Claude requests
+    #[serde(default)]
+    pub full_tool_definitions: bool,
+}
+
+/// Who may use the proxy
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessConfig {
+    /// Client networks allowed to connect ("127.0.0.1", "10.0.0.0/8", "::1");
+    /// empty allows every client
+    #[serde(default)]
+    pub allowed_clients: Vec<String>,
+
+    /// Require `Proxy-Authorization` basic auth with this username
+    #[serde(default)]
+    pub username: Option<String>,
+
+    /// Password for proxy basic auth
+    #[serde(default)]
+    pub password: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FilteringConfig {
+    /// Hosts whose CONNECT tunnels are decrypted: exact host, `*.domain`,
+    /// `*`, optionally with `:port`; empty intercepts every host
     #[serde(default = "default_target_hosts")]
     pub target_hosts: Vec<String>,
 
+    /// Hosts that are always tunneled untouched, even if they match `target_hosts`
+    #[serde(default)]
+    pub never_intercept: Vec<String>,
+
+    /// Paths to record: globs (`/v1/messages*`, `/api/**`) or `re:` regexes;
+    /// empty records every path
     #[serde(default)]
     pub capture_patterns: Vec<String>,
+
+    /// Paths never recorded in full, even when they match `capture_patterns`
+    #[serde(default)]
+    pub exclude_patterns: Vec<String>,
+
+    /// HTTP methods to record; empty records every method
+    #[serde(default)]
+    pub capture_methods: Vec<String>,
+
+    /// Response statuses to record: exact ("429"), class ("5xx") or range
+    /// ("400-499"); empty records every status
+    #[serde(default)]
+    pub capture_status: Vec<String>,
+
+    /// Record exchanges that don't match as metadata-only entries (no bodies)
+    /// instead of skipping them
+    #[serde(default)]
+    pub record_uncaptured_metadata: bool,
+}
+
+/// Connection settings for the shared upstream client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamConfig {
+    /// Maximum idle keep-alive connections kept per upstream host
+    #[serde(default = "default_pool_max_idle_per_host")]
+    pub pool_max_idle_per_host: usize,
+
+    /// Seconds an idle pooled connection is kept before being closed
+    #[serde(default = "default_pool_idle_timeout_secs")]
+    pub pool_idle_timeout_secs: u64,
+
+    /// Seconds allowed for opening a connection (DNS, TCP connect and TLS),
+    /// passthrough tunnels included (0 disables)
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+
+    /// Seconds to wait for the response headers or the next body chunk (0 disables)
+    ///
+    /// Long enough for extended thinking to go quiet between stream events.
+    #[serde(default = "default_read_timeout_secs")]
+    pub read_timeout_secs: u64,
+
+    /// Seconds allowed for the whole exchange, streamed body included (0 disables)
+    #[serde(default)]
+    pub total_timeout_secs: u64,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+/// Retries of failed upstream requests (off unless `max_retries` is set)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Retries after the first attempt; 0 disables retrying
+    #[serde(default)]
+    pub max_retries: u32,
+
+    /// Response statuses retried for any method, honouring `retry-after`
+    #[serde(default = "default_retry_statuses")]
+    pub retry_statuses: Vec<u16>,
+
+    /// Delay before the first retry when the response gives no `retry-after`;
+    /// doubled on each further retry
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+
+    /// Longest delay to wait; a longer `retry-after` is returned to the client instead
+    #[serde(default = "default_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+}
+
+/// Upstream (chained) HTTP proxy, reached with HTTP CONNECT
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamProxyConfig {
+    /// Proxy address, e.g. "http://proxy.corp.example:3128"
+    pub url: String,
+
+    /// Username for basic authentication
+    #[serde(default)]
+    pub username: Option<String>,
+
+    /// Password for basic authentication
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Hosts reached directly, in addition to the `NO_PROXY` environment variable
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+}
+
+/// Per-model token prices used to compute the cost of each API call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PricingConfig {
+    /// Prices keyed by model name prefix (e.g., "claude-sonnet-4" matches
+    /// "claude-sonnet-4-5-20250929"); the longest matching prefix wins.
+    /// Configured entries are merged over the built-in table.
+    #[serde(default = "default_model_prices", deserialize_with = "merge_model_prices")]
+    pub models: HashMap<String, ModelPrice>,
+}
+
+/// Token prices for one model family, in USD per million tokens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input_per_mtok: f64,
+    pub output_per_mtok: f64,
+    #[serde(default)]
+    pub cache_write_per_mtok: f64,
+    #[serde(default)]
+    pub cache_read_per_mtok: f64,
 }
 
 impl Default for ProxyConfig {
@@ -61,9 +233,14 @@ impl Default for ProxyConfig {
         Self {
             listen_addr: default_listen_addr(),
             listen_port: default_listen_port(),
+            shutdown_timeout_secs: default_shutdown_timeout_secs(),
             tls: TlsConfig::default(),
             recording: RecordingConfig::default(),
             filtering: FilteringConfig::default(),
+            access: AccessConfig::default(),
+            pricing: PricingConfig::default(),
+            upstream: UpstreamConfig::default(),
+            upstream_proxy: None,
         }
     }
 }
@@ -84,6 +261,10 @@ impl Default for RecordingConfig {
             pretty_print: true,
             include_bodies: true,
             max_body_size: default_max_body_size(),
+            keep_sse_events: false,
+            blob_threshold_bytes: None,
+            delta_encode_requests: false,
+            full_tool_definitions: false,
         }
     }
 }
@@ -92,11 +273,75 @@ impl Default for FilteringConfig {
     fn default() -> Self {
         Self {
             target_hosts: default_target_hosts(),
+            never_intercept: vec![],
             capture_patterns: vec![],
+            exclude_patterns: vec![],
+            capture_methods: vec![],
+            capture_status: vec![],
+            record_uncaptured_metadata: false,
         }
     }
 }
 
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            pool_max_idle_per_host: default_pool_max_idle_per_host(),
+            pool_idle_timeout_secs: default_pool_idle_timeout_secs(),
+            connect_timeout_secs: default_connect_timeout_secs(),
+            read_timeout_secs: default_read_timeout_secs(),
+            total_timeout_secs: 0,
+            retry: RetryConfig::default(),
+        }
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            retry_statuses: default_retry_statuses(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_secs: default_max_backoff_secs(),
+        }
+    }
+}
+
+impl Default for PricingConfig {
+    fn default() -> Self {
+        Self {
+            models: default_model_prices(),
+        }
+    }
+}
+
+impl PricingConfig {
+    /// Find the price entry for a model using longest-prefix matching
+    pub fn price_for(&self, model: &str) -> Option<&ModelPrice> {
+        self.models
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, price)| price)
+    }
+
+    /// Compute the cost of a call in USD, if the model has a known price
+    pub fn cost_usd(&self, model: &str, usage: &MessageUsage) -> Option<f64> {
+        self.price_for(model).map(|price| price.cost_usd(usage))
+    }
+}
+
+impl ModelPrice {
+    /// Compute the cost of the given token usage in USD
+    pub fn cost_usd(&self, usage: &MessageUsage) -> f64 {
+        (usage.input_tokens as f64 * self.input_per_mtok
+            + usage.output_tokens as f64 * self.output_per_mtok
+            + usage.cache_creation_input_tokens as f64 * self.cache_write_per_mtok
+            + usage.cache_read_input_tokens as f64 * self.cache_read_per_mtok)
+            / 1_000_000.0
+    }
+}
+
 impl ProxyConfig {
     /// Load configuration from a TOML file
     pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
@@ -153,11 +398,15 @@ fn default_listen_port() -> u16 {
     6969
 }
 
+fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
 fn default_cert_dir() -> PathBuf {
     let home = std::env::var("HOME")
         .ok()
         .map(PathBuf::from)
-        .or_else(|| dirs::home_dir())
+        .or_else(dirs::home_dir)
         .unwrap_or_else(|| PathBuf::from("."));
     home.join(".local-logger").join("certs")
 }
@@ -166,7 +415,7 @@ fn default_output_dir() -> PathBuf {
     let home = std::env::var("HOME")
         .ok()
         .map(PathBuf::from)
-        .or_else(|| dirs::home_dir())
+        .or_else(dirs::home_dir)
         .unwrap_or_else(|| PathBuf::from("."));
     home.join(".local-logger")
 }
@@ -179,6 +428,67 @@ fn default_target_hosts() -> Vec<String> {
     vec!["api.anthropic.com".to_string()]
 }
 
+fn default_pool_max_idle_per_host() -> usize {
+    16
+}
+
+fn default_pool_idle_timeout_secs() -> u64 {
+    90
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    10
+}
+
+fn default_read_timeout_secs() -> u64 {
+    600
+}
+
+fn default_retry_statuses() -> Vec<u16> {
+    // Rate limited and overloaded
+    vec![429, 529]
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_backoff_secs() -> u64 {
+    30
+}
+
+fn default_model_prices() -> HashMap<String, ModelPrice> {
+    let price = |input: f64, output: f64| ModelPrice {
+        input_per_mtok: input,
+        output_per_mtok: output,
+        cache_write_per_mtok: input * 1.25,
+        cache_read_per_mtok: input * 0.1,
+    };
+
+    HashMap::from([
+        ("claude-opus-4-5".to_string(), price(5.0, 25.0)),
+        ("claude-opus-4".to_string(), price(15.0, 75.0)),
+        ("claude-sonnet-4".to_string(), price(3.0, 15.0)),
+        ("claude-3-7-sonnet".to_string(), price(3.0, 15.0)),
+        ("claude-3-5-sonnet".to_string(), price(3.0, 15.0)),
+        ("claude-haiku-4-5".to_string(), price(1.0, 5.0)),
+        ("claude-3-5-haiku".to_string(), price(0.8, 4.0)),
+        ("claude-3-haiku".to_string(), price(0.25, 1.25)),
+    ])
+}
+
+/// Configured model prices layered over the defaults, so adding one model
+/// does not drop the prices of all the others
+fn merge_model_prices<'de, D>(deserializer: D) -> Result<HashMap<String, ModelPrice>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let configured = HashMap::<String, ModelPrice>::deserialize(deserializer)?;
+    let mut models = default_model_prices();
+    models.extend(configured);
+    Ok(models)
+}
+
 fn default_true() -> bool {
     true
 }
@@ -209,6 +519,116 @@ mod tests {
         assert_eq!(config.recording.max_body_size, loaded.recording.max_body_size);
     }
 
+    #[test]
+    fn test_price_lookup_uses_longest_prefix() {
+        let pricing = PricingConfig::default();
+
+        let opus_45 = pricing.price_for("claude-opus-4-5-20251101").unwrap();
+        assert_eq!(opus_45.input_per_mtok, 5.0);
+
+        let opus_41 = pricing.price_for("claude-opus-4-1-20250805").unwrap();
+        assert_eq!(opus_41.input_per_mtok, 15.0);
+
+        assert!(pricing.price_for("gpt-4o").is_none());
+    }
+
+    #[test]
+    fn test_cost_calculation() {
+        let pricing = PricingConfig::default();
+        let usage = MessageUsage {
+            input_tokens: 1_000_000,
+            output_tokens: 100_000,
+            cache_creation_input_tokens: 200_000,
+            cache_read_input_tokens: 2_000_000,
+        };
+
+        // 3.00 input + 1.50 output + 0.75 cache write + 0.60 cache read
+        let cost = pricing.cost_usd("claude-sonnet-4-5", &usage).unwrap();
+        assert!((cost - 5.85).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_pricing_from_toml() {
+        let config: ProxyConfig = toml::from_str(
+            r#"
+            [pricing.models.custom-model]
+            input_per_mtok = 2.0
+            output_per_mtok = 8.0
+            "#,
+        )
+        .unwrap();
+
+        let price = config.pricing.price_for("custom-model-v2").unwrap();
+        assert_eq!(price.output_per_mtok, 8.0);
+        assert_eq!(price.cache_read_per_mtok, 0.0);
+    }
+
+    #[test]
+    fn test_custom_prices_merge_over_defaults() {
+        let config: ProxyConfig = toml::from_str(
+            r#"
+            [pricing.models.custom]
+            input_per_mtok = 2.0
+            output_per_mtok = 8.0
+
+            [pricing.models.claude-3-haiku]
+            input_per_mtok = 0.5
+            output_per_mtok = 2.5
+            "#,
+        )
+        .unwrap();
+
+        let sonnet = config.pricing.price_for("claude-sonnet-4").unwrap();
+        assert_eq!(sonnet.input_per_mtok, 3.0);
+        assert_eq!(sonnet.output_per_mtok, 15.0);
+        assert_eq!(config.pricing.price_for("custom").unwrap().input_per_mtok, 2.0);
+        assert_eq!(config.pricing.price_for("claude-3-haiku-20240307").unwrap().input_per_mtok, 0.5);
+    }
+
+    #[test]
+    fn test_upstream_proxy_from_toml() {
+        let config: ProxyConfig = toml::from_str(
+            r#"
+            [upstream_proxy]
+            url = "http://proxy.corp.example:3128"
+            username = "alice"
+            password = "secret"
+            no_proxy = ["localhost"]
+            "#,
+        )
+        .unwrap();
+
+        let proxy = config.upstream_proxy.unwrap();
+        assert_eq!(proxy.url, "http://proxy.corp.example:3128");
+        assert_eq!(proxy.username.as_deref(), Some("alice"));
+        assert_eq!(proxy.no_proxy, vec!["localhost"]);
+
+        assert!(ProxyConfig::default().upstream_proxy.is_none());
+    }
+
+    #[test]
+    fn test_upstream_timeouts_and_retry_from_toml() {
+        let config: ProxyConfig = toml::from_str(
+            r#"
+            [upstream]
+            read_timeout_secs = 0
+            total_timeout_secs = 900
+
+            [upstream.retry]
+            max_retries = 2
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.upstream.connect_timeout_secs, 10);
+        assert_eq!(config.upstream.read_timeout_secs, 0);
+        assert_eq!(config.upstream.total_timeout_secs, 900);
+        assert_eq!(config.upstream.retry.max_retries, 2);
+        assert_eq!(config.upstream.retry.retry_statuses, vec![429, 529]);
+
+        assert_eq!(ProxyConfig::default().upstream.retry.max_retries, 0);
+    }
+
     #[test]
     #[serial]
     fn test_from_env() {
@@ -1,26 +1,58 @@
 //! HTTP/HTTPS proxy server with MITM capabilities
 
+use crate::access::{self, ClientAllowList};
+use crate::api_request;
+use crate::blob_store::BlobStore;
+use crate::capture::{Capture, CaptureFilter};
 use crate::certificate_manager::CertificateManager;
+use crate::delta::DeltaEncoder;
+use crate::intercept;
 use crate::log_writer::LogWriter;
 use crate::proxy_config::ProxyConfig;
-use crate::schema::{BodyData, LogEntry, UrlComponents, redact_sensitive_headers};
+use crate::rate_limit;
+use crate::retry::RetryPolicy;
+use crate::schema::{
+    BodyContent, BodyData, ExchangeTiming, HeaderValueData, Headers, LogEntry, Origin, ProxyRequestEvent, ProxyResponseEvent,
+    SessionSource, UpstreamErrorKind, UrlComponents, UsageRecord,
+};
+use crate::session::SessionCorrelator;
+use crate::shutdown::{self, InFlight, InFlightGuard};
+use crate::sse;
+use crate::upstream::{self, Deadline, UpstreamClient, UpstreamProxy};
+use crate::usage;
 use anyhow::{Context, Result};
 use bytes::Bytes;
 use http_body_util::{BodyExt, Full};
-use hyper::body::Incoming;
+use hyper::body::{Body, Frame, Incoming, SizeHint};
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
-use hyper::{Method, Request, Response, StatusCode, Uri};
-use hyper_util::rt::TokioIo;
+use hyper::{Method, Request, Response, StatusCode, Uri, Version};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
 use rustls::ServerConfig;
 use std::collections::HashMap;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{self, Poll};
+use std::time::{Duration, Instant};
 use tokio::net::{TcpListener, TcpStream};
 use tokio_rustls::TlsAcceptor;
+use tracing::Instrument;
 use uuid::Uuid;
 
-type BoxBody = http_body_util::combinators::UnsyncBoxBody<Bytes, hyper::Error>;
+/// Interval between HTTP/2 pings on idle client connections
+const H2_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(20);
+
+/// How long a client has to answer an HTTP/2 ping before its connection is closed
+const H2_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Concurrent requests allowed per HTTP/2 client connection
+const H2_MAX_CONCURRENT_STREAMS: u32 = 100;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type BoxBody = http_body_util::combinators::UnsyncBoxBody<Bytes, BoxError>;
 
 fn full<T: Into<Bytes>>(chunk: T) -> BoxBody {
     Full::new(chunk.into())
@@ -28,25 +60,220 @@ fn full<T: Into<Bytes>>(chunk: T) -> BoxBody {
         .boxed_unsync()
 }
 
-pub struct ProxyServer {
+/// Response body that forwards frames to the client while keeping a copy
+///
+/// Used for streamed responses (server-sent events) so chunks reach the
+/// client as soon as they arrive. The recorded copy is logged as a single
+/// response event once the stream ends, errors, or is dropped by the client.
+/// The stream fails with a timeout when the upstream misses its deadline.
+struct RecordingBody {
+    inner: Incoming,
+    recorder: Option<ResponseRecorder>,
+    deadline: Deadline,
+    timer: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl RecordingBody {
+    fn new(inner: Incoming, recorder: Option<ResponseRecorder>, deadline: Deadline) -> Self {
+        Self {
+            inner,
+            recorder,
+            deadline,
+            timer: deadline.next().map(|at| Box::pin(tokio::time::sleep_until(at))),
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            recorder.finish();
+        }
+    }
+}
+
+/// Accumulates a streamed response until it can be logged
+struct ResponseRecorder {
+    ctx: Arc<ProxyContext>,
+    exchange: Exchange,
+    status: StatusCode,
+    headers: hyper::HeaderMap,
+    buffer: Vec<u8>,
+    start: Instant,
+    ttfb_ms: Option<u64>,
+    connection_reused: Option<bool>,
+    timing: ExchangeTiming,
+    capture: Capture,
+    /// Keeps the exchange in flight until its response event is written
+    in_flight: Option<InFlightGuard>,
+}
+
+impl ResponseRecorder {
+    fn record_chunk(&mut self, chunk: &Bytes) {
+        let start = self.start;
+        self.ttfb_ms
+            .get_or_insert_with(|| start.elapsed().as_millis() as u64);
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// Write the recorded response in the background
+    fn finish(self) {
+        let duration_ms = self.start.elapsed().as_millis() as u64;
+        tokio::spawn(async move {
+            let response = UpstreamResponse {
+                status: self.status,
+                headers: &self.headers,
+                body: &Bytes::from(self.buffer),
+                duration_ms,
+                ttfb_ms: self.ttfb_ms,
+                connection_reused: self.connection_reused,
+                timing: self.timing,
+            };
+            ProxyServer::log_response(&self.ctx, &self.exchange, response, self.capture).await;
+            drop(self.in_flight);
+        });
+    }
+}
+
+impl Body for RecordingBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.as_mut().get_mut();
+        let poll = Pin::new(&mut this.inner).poll_frame(cx);
+
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let (Some(data), Some(recorder)) = (frame.data_ref(), this.recorder.as_mut()) {
+                    recorder.record_chunk(data);
+                }
+                if let (Some(timer), Some(at)) = (this.timer.as_mut(), this.deadline.next()) {
+                    timer.as_mut().reset(at);
+                }
+            }
+            Poll::Ready(Some(Err(_))) | Poll::Ready(None) => this.finish(),
+            Poll::Pending => {
+                if this.timer.as_mut().is_some_and(|timer| timer.as_mut().poll(cx).is_ready()) {
+                    tracing::warn!("Upstream stream timed out");
+                    this.timer = None;
+                    this.finish();
+                    return Poll::Ready(Some(Err(upstream::timed_out().into())));
+                }
+            }
+        }
+
+        poll.map_err(Into::into)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for RecordingBody {
+    fn drop(&mut self) {
+        // Client went away mid-stream: still log what was received
+        self.finish();
+    }
+}
+
+/// State shared by every connection and exchange of a proxy run
+struct ProxyContext {
     config: ProxyConfig,
-    cert_manager: Arc<CertificateManager>,
+    cert_manager: CertificateManager,
     log_writer: Arc<LogWriter>,
+    client: UpstreamClient,
+    capture: CaptureFilter,
+    in_flight: InFlight,
+    deltas: DeltaEncoder,
+    sessions: SessionCorrelator,
 }
 
-impl ProxyServer {
-    pub fn new(config: ProxyConfig, log_writer: Arc<LogWriter>) -> Result<Self> {
-        let cert_manager = Arc::new(CertificateManager::new(&config.tls.cert_dir)?);
+impl ProxyContext {
+    fn new(config: ProxyConfig, log_writer: Arc<LogWriter>) -> Result<Self> {
+        let cert_manager = CertificateManager::new(&config.tls.cert_dir)?;
+
+        // One pooled client for the lifetime of the proxy so upstream
+        // keep-alive connections are reused across requests
+        let client = upstream::build_client(&config.upstream, config.upstream_proxy.as_ref())?;
+
+        let capture =
+            CaptureFilter::new(&config.filtering).context("Invalid capture filter configuration")?;
+
+        let sessions = SessionCorrelator::new(log_writer.clone());
 
         Ok(Self {
             config,
             cert_manager,
             log_writer,
+            client,
+            capture,
+            in_flight: InFlight::default(),
+            deltas: DeltaEncoder::default(),
+            sessions,
+        })
+    }
+}
+
+/// Ids tying together the events of one exchange
+#[derive(Clone)]
+struct Exchange {
+    request_id: Uuid,
+    session_id: String,
+    correlation_id: String,
+    origin: Origin,
+}
+
+/// A client request as recorded, the same for each of its attempts
+struct ClientRequest<'a> {
+    method: &'a Method,
+    uri: &'a Uri,
+    version: Version,
+    headers: &'a hyper::HeaderMap,
+    body: &'a Bytes,
+    session_source: Option<SessionSource>,
+    tls_handshake_ms: Option<u64>,
+}
+
+/// An upstream response as recorded
+struct UpstreamResponse<'a> {
+    status: StatusCode,
+    headers: &'a hyper::HeaderMap,
+    body: &'a Bytes,
+    duration_ms: u64,
+    ttfb_ms: Option<u64>,
+    connection_reused: Option<bool>,
+    timing: ExchangeTiming,
+}
+
+pub struct ProxyServer {
+    ctx: Arc<ProxyContext>,
+    allow_list: ClientAllowList,
+    /// Origin of this proxy run, specialized per client connection
+    origin: Origin,
+}
+
+impl ProxyServer {
+    pub fn new(config: ProxyConfig, log_writer: Arc<LogWriter>, proxy_run_id: Uuid) -> Result<Self> {
+        let allow_list =
+            ClientAllowList::new(&config.access).context("Invalid allowed_clients configuration")?;
+
+        Ok(Self {
+            ctx: Arc::new(ProxyContext::new(config, log_writer)?),
+            allow_list,
+            origin: Origin::run(proxy_run_id),
         })
     }
 
     pub async fn run(&self) -> Result<()> {
-        let addr = SocketAddr::new(self.config.listen_addr, self.config.listen_port);
+        let config = &self.ctx.config;
+        let addr = SocketAddr::new(config.listen_addr, config.listen_port);
         let listener = TcpListener::bind(addr)
             .await
             .context("Failed to bind proxy server")?;
@@ -56,74 +283,154 @@ impl ProxyServer {
         tracing::info!("  export HTTP_PROXY=http://{}", addr);
         tracing::info!("  export HTTPS_PROXY=http://{}", addr);
 
-        loop {
-            let (stream, peer_addr) = listener.accept().await?;
-            tracing::debug!("Accepted connection from {}", peer_addr);
+        if !addr.ip().is_loopback()
+            && config.access.allowed_clients.is_empty()
+            && config.access.username.is_none()
+        {
+            tracing::warn!(
+                "Listening on {} without allowed_clients or proxy credentials: anyone who can reach it can use the proxy",
+                addr
+            );
+        }
 
-            let config = self.config.clone();
-            let cert_manager = self.cert_manager.clone();
-            let log_writer = self.log_writer.clone();
+        let shutdown = shutdown::signal();
+        tokio::pin!(shutdown);
+        let mut connections: u64 = 0;
 
+        loop {
+            let (stream, peer_addr) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = &mut shutdown => break,
+            };
+
+            if !self.allow_list.allows(peer_addr.ip()) {
+                tracing::warn!("Rejected connection from {}: not in allowed_clients", peer_addr);
+                tokio::spawn(Self::reject_connection(stream));
+                continue;
+            }
+            // Events of the connection, including tracing output, carry its id
+            connections += 1;
+            let origin = self.origin.connection(connections, peer_addr);
+            let span = tracing::info_span!("connection", connection_id = connections, peer_addr = %peer_addr);
+            span.in_scope(|| tracing::debug!("Accepted connection from {}", peer_addr));
+
+            let ctx = self.ctx.clone();
             tokio::spawn(async move {
-                if let Err(e) = Self::handle_connection(stream, config, cert_manager, log_writer).await {
+                if let Err(e) = Self::handle_connection(stream, peer_addr, ctx, origin).await {
                     tracing::error!("Connection error: {}", e);
                 }
-            });
+            }.instrument(span));
+        }
+
+        // Stop accepting, close idle connections once their current requests
+        // are answered, then give in-flight exchanges time to finish
+        drop(listener);
+        let in_flight = &self.ctx.in_flight;
+        let connections = in_flight.close_connections();
+        tracing::info!(
+            "Shutting down, closing {} connection(s) and waiting for {} in-flight exchange(s)",
+            connections,
+            in_flight.len()
+        );
+
+        let timeout = std::time::Duration::from_secs(config.shutdown_timeout_secs);
+        for (request_id, pending) in in_flight.drain(timeout).await {
+            tracing::warn!("Exchange {} did not finish before shutdown", request_id);
+            let entry = LogEntry::new_proxy_aborted(
+                pending.session_id,
+                pending.correlation_id,
+                request_id,
+                pending.started.elapsed().as_millis() as u64,
+                "proxy shut down before the exchange finished".to_string(),
+            )
+            .with_origin(&pending.origin);
+            let _ = self.ctx.log_writer.write_async(entry).await;
         }
+
+        tracing::info!("Proxy server stopped");
+        Ok(())
+    }
+
+    /// Answer every request on a refused connection with 403 and close it
+    async fn reject_connection(stream: TcpStream) {
+        let service = service_fn(|_req| async {
+            Ok::<_, std::convert::Infallible>(
+                Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .header("connection", "close")
+                    .body(full("Client address not allowed\n"))
+                    .unwrap(),
+            )
+        });
+
+        let serve = http1::Builder::new()
+            .keep_alive(false)
+            .serve_connection(TokioIo::new(stream), service);
+        let _ = tokio::time::timeout(std::time::Duration::from_secs(10), serve).await;
     }
 
     async fn handle_connection(
         stream: TcpStream,
-        config: ProxyConfig,
-        cert_manager: Arc<CertificateManager>,
-        log_writer: Arc<LogWriter>,
+        peer_addr: SocketAddr,
+        ctx: Arc<ProxyContext>,
+        origin: Origin,
     ) -> Result<()> {
         let io = TokioIo::new(stream);
+        let connections = ctx.in_flight.clone();
 
-        let service = service_fn(move |req| {
-            Self::proxy_request(
-                req,
-                config.clone(),
-                cert_manager.clone(),
-                log_writer.clone(),
-            )
-        });
+        let service = service_fn(move |req| Self::proxy_request(req, peer_addr, ctx.clone(), origin.clone()));
 
-        http1::Builder::new()
+        let conn = http1::Builder::new()
             .preserve_header_case(true)
             .title_case_headers(true)
             .serve_connection(io, service)
-            .with_upgrades()
-            .await?;
+            .with_upgrades();
+        connections.serve(conn, |conn| conn.graceful_shutdown()).await?;
 
         Ok(())
     }
 
     async fn proxy_request(
-        req: Request<Incoming>,
-        config: ProxyConfig,
-        cert_manager: Arc<CertificateManager>,
-        log_writer: Arc<LogWriter>,
+        mut req: Request<Incoming>,
+        peer_addr: SocketAddr,
+        ctx: Arc<ProxyContext>,
+        origin: Origin,
     ) -> Result<Response<BoxBody>, Box<dyn std::error::Error + Send + Sync>> {
         let method = req.method().clone();
         let uri = req.uri().clone();
 
         tracing::info!("{} {}", method, uri);
 
+        if ctx.config.access.username.is_some() {
+            if !access::proxy_auth_ok(&ctx.config.access, req.headers()) {
+                tracing::warn!(
+                    "Rejected {} {} from {}: missing or invalid proxy credentials",
+                    method,
+                    uri,
+                    peer_addr
+                );
+                return Ok(Response::builder()
+                    .status(StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+                    .header("proxy-authenticate", "Basic realm=\"local-logger\"")
+                    .body(full("Proxy authentication required\n"))?);
+            }
+            // Credentials are for this hop only
+            req.headers_mut().remove(hyper::header::PROXY_AUTHORIZATION);
+        }
+
         // Handle CONNECT method for HTTPS
         if method == Method::CONNECT {
-            return Self::handle_connect(req, config, cert_manager, log_writer).await;
+            return Self::handle_connect(req, ctx, origin).await;
         }
 
         // Handle regular HTTP proxy
-        Self::handle_http_proxy(req, config, log_writer).await
+        Self::handle_http_proxy(req, ctx, origin).await
     }
 
     async fn handle_connect(
         req: Request<Incoming>,
-        config: ProxyConfig,
-        cert_manager: Arc<CertificateManager>,
-        log_writer: Arc<LogWriter>,
+        ctx: Arc<ProxyContext>,
+        origin: Origin,
     ) -> Result<Response<BoxBody>, Box<dyn std::error::Error + Send + Sync>> {
         // Extract full authority (hostname:port)
         let authority = req.uri()
@@ -135,16 +442,16 @@ impl ProxyServer {
             .as_str()
             .to_string();
 
-        // Extract hostname for interception filtering
-        let hostname = authority.split(':').next().unwrap_or(&authority);
-
-        tracing::info!("CONNECT to {}", authority);
-
-        // Check if this host should be intercepted
-        let should_intercept = config.filtering.target_hosts.is_empty()
-            || config.filtering.target_hosts.iter().any(|h| hostname.contains(h));
+        // Check if this host should be intercepted, recording why
+        let decision = intercept::decide(&ctx.config.filtering, &authority);
+        tracing::info!(
+            "CONNECT to {} ({}: {})",
+            authority,
+            if decision.intercept { "intercept" } else { "passthrough" },
+            decision.reason
+        );
 
-        if should_intercept {
+        if decision.intercept {
             // MITM mode: intercept and decrypt
             // Extract upgrade future BEFORE moving req into spawned task
             // This is critical for hyper's upgrade mechanism to work correctly
@@ -154,21 +461,13 @@ impl ProxyServer {
                 match upgrade.await {
                     Ok(upgraded) => {
                         tracing::debug!("MITM upgrade successful for {}", authority);
-                        if let Err(e) = Self::mitm_tunnel(
-                            upgraded,
-                            authority.clone(),
-                            config,
-                            cert_manager,
-                            log_writer,
-                        )
-                        .await
-                        {
+                        if let Err(e) = Self::mitm_tunnel(upgraded, authority.clone(), ctx, origin).await {
                             tracing::error!("MITM tunnel error for {}: {}", authority, e);
                         }
                     }
                     Err(e) => tracing::error!("MITM upgrade error for {}: {}", authority, e),
                 }
-            });
+            }.in_current_span());
 
             Ok(Response::new(full("")))
         } else {
@@ -183,71 +482,105 @@ impl ProxyServer {
                 match upgrade.await {
                     Ok(upgraded) => {
                         tracing::debug!("Passthrough upgrade successful for {}", authority);
-                        if let Err(e) = Self::tunnel(upgraded, authority.clone()).await {
+                        if let Err(e) = Self::tunnel(upgraded, authority.clone(), &ctx.config).await {
                             tracing::error!("Tunnel error for {}: {}", authority, e);
                         }
                     }
                     Err(e) => tracing::error!("Passthrough upgrade error for {}: {}", authority, e),
                 }
-            });
+            }.in_current_span());
 
             Ok(Response::new(full("")))
         }
     }
 
-    async fn mitm_tunnel(
-        upgraded: hyper::upgrade::Upgraded,
-        host: String,
-        config: ProxyConfig,
-        cert_manager: Arc<CertificateManager>,
-        log_writer: Arc<LogWriter>,
-    ) -> Result<()> {
+    async fn mitm_tunnel<I>(upgraded: I, host: String, ctx: Arc<ProxyContext>, origin: Origin) -> Result<()>
+    where
+        I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+    {
         let hostname = host.split(':').next().unwrap_or(&host);
 
         // Get or generate certificate for this host
-        let (certs, key) = cert_manager.get_certificate(hostname).await?;
+        let (certs, key) = ctx.cert_manager.get_certificate(hostname).await?;
 
         // Build TLS server config
-        let tls_config = ServerConfig::builder()
+        let mut tls_config = ServerConfig::builder()
             .with_no_client_auth()
             .with_single_cert(certs, key)
             .map_err(|e| anyhow::anyhow!("TLS config error: {}", e))?;
 
+        // Offer HTTP/2 so clients that prefer it don't have to fall back
+        tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
         let tls_acceptor = TlsAcceptor::from(Arc::new(tls_config));
 
         // Wrap the upgraded connection with TLS
+        let tls_start = Instant::now();
         let tls_stream = tls_acceptor
             .accept(TokioIo::new(upgraded))
             .await
             .map_err(|e| anyhow::anyhow!("TLS accept error: {}", e))?;
+        let client_tls_ms = tls_start.elapsed().as_millis() as u64;
+
+        let alpn = tls_stream
+            .get_ref()
+            .1
+            .alpn_protocol()
+            .map(|p| String::from_utf8_lossy(p).to_string());
+        tracing::debug!(
+            "TLS established for {} in {}ms (ALPN: {})",
+            host,
+            client_tls_ms,
+            alpn.as_deref().unwrap_or("none")
+        );
 
         // Now handle HTTPS traffic
         let io = TokioIo::new(tls_stream);
+        let connections = ctx.in_flight.clone();
 
         let service = service_fn(move |req| {
-            Self::handle_https_request(
-                req,
-                host.clone(),
-                config.clone(),
-                log_writer.clone(),
-            )
+            Self::handle_https_request(req, host.clone(), client_tls_ms, ctx.clone(), origin.clone())
         });
 
-        http1::Builder::new()
+        // Serve HTTP/1.1 or HTTP/2 depending on what the client speaks
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http1()
             .preserve_header_case(true)
-            .title_case_headers(true)
-            .serve_connection(io, service)
+            .title_case_headers(true);
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .keep_alive_interval(H2_KEEP_ALIVE_INTERVAL)
+            .keep_alive_timeout(H2_KEEP_ALIVE_TIMEOUT)
+            .max_concurrent_streams(H2_MAX_CONCURRENT_STREAMS);
+
+        let conn = builder.serve_connection(io, service);
+        connections
+            .serve(conn, |conn| conn.graceful_shutdown())
             .await
             .map_err(|e| anyhow::anyhow!("HTTPS serve error: {}", e))?;
 
         Ok(())
     }
 
-    async fn tunnel(upgraded: hyper::upgrade::Upgraded, host: String) -> Result<()> {
-        // Connect to the target
-        let target_stream = TcpStream::connect(&host)
-            .await
-            .context("Failed to connect to target")?;
+    async fn tunnel(upgraded: hyper::upgrade::Upgraded, host: String, config: &ProxyConfig) -> Result<()> {
+        let proxy = config
+            .upstream_proxy
+            .as_ref()
+            .map(UpstreamProxy::from_config)
+            .transpose()?;
+
+        let (hostname, port) = host
+            .rsplit_once(':')
+            .and_then(|(hostname, port)| Some((hostname, port.parse().ok()?)))
+            .unwrap_or((host.as_str(), 443));
+
+        // Connect to the target, through the upstream proxy if configured
+        let (target_stream, _, _) =
+            upstream::connect_tcp_with_timeout(proxy.as_ref(), hostname, port, &config.upstream)
+                .await
+                .context("Failed to connect to target")?;
 
         // Wrap upgraded connection in TokioIo for AsyncRead/AsyncWrite
         let mut client = TokioIo::new(upgraded);
@@ -267,8 +600,9 @@ impl ProxyServer {
     async fn handle_https_request(
         req: Request<Incoming>,
         host: String,
-        config: ProxyConfig,
-        log_writer: Arc<LogWriter>,
+        client_tls_ms: u64,
+        ctx: Arc<ProxyContext>,
+        origin: Origin,
     ) -> Result<Response<BoxBody>, Box<dyn std::error::Error + Send + Sync>> {
         let method = req.method().clone();
         let path = req.uri().path().to_string();
@@ -286,30 +620,29 @@ impl ProxyServer {
         tracing::info!("HTTPS: {} {}", method, full_uri);
 
         // Forward the request
-        Self::forward_request(req, full_uri.parse().unwrap(), config, log_writer).await
+        Self::forward_request(req, full_uri.parse().unwrap(), Some(client_tls_ms), ctx, origin).await
     }
 
     async fn handle_http_proxy(
         req: Request<Incoming>,
-        config: ProxyConfig,
-        log_writer: Arc<LogWriter>,
+        ctx: Arc<ProxyContext>,
+        origin: Origin,
     ) -> Result<Response<BoxBody>, Box<dyn std::error::Error + Send + Sync>> {
         let uri = req.uri().clone();
-        Self::forward_request(req, uri, config, log_writer).await
+        Self::forward_request(req, uri, None, ctx, origin).await
     }
 
     async fn forward_request(
         req: Request<Incoming>,
         uri: Uri,
-        config: ProxyConfig,
-        log_writer: Arc<LogWriter>,
+        client_tls_ms: Option<u64>,
+        ctx: Arc<ProxyContext>,
+        origin: Origin,
     ) -> Result<Response<BoxBody>, Box<dyn std::error::Error + Send + Sync>> {
-        use std::time::Instant;
-
-        let request_id = Uuid::new_v4();
-        let session_id = Uuid::new_v4();
-        let correlation_id = session_id.to_string();
+        let received = Instant::now();
+        let correlation_id = Uuid::new_v4().to_string();
         let method = req.method().clone();
+        let version = req.version();
         let headers = req.headers().clone();
 
         // Collect request body
@@ -319,104 +652,329 @@ impl ProxyServer {
             .await?
             .to_bytes();
 
-        // Log request
-        if config.recording.include_bodies {
-            Self::log_request(
-                &request_id,
-                &session_id.to_string(),
-                &correlation_id,
-                &method,
-                &uri,
-                &headers,
-                &body_bytes,
-                &config,
-                &log_writer,
-            )
-            .await;
-        }
+        // Record under the Claude Code session that sent the request, so
+        // proxy events join with its hook events
+        let config = &ctx.config;
+        let (session_id, session_source) = match ctx.sessions.resolve(uri.path(), &headers, &body_bytes).await {
+            Some((id, source)) => (id, Some(source)),
+            None => (correlation_id.clone(), None),
+        };
 
-        // Start timing
-        let start = Instant::now();
+        // Decide what to record; with status filters the request event waits
+        // for the response status
+        let early_capture = ctx.capture.decide_request(method.as_str(), uri.path());
+        let request = ClientRequest {
+            method: &method,
+            uri: &uri,
+            version,
+            headers: &headers,
+            body: &body_bytes,
+            session_source,
+            tls_handshake_ms: client_tls_ms,
+        };
 
-        // Create HTTPS client
-        let https = hyper_rustls::HttpsConnectorBuilder::new()
-            .with_native_roots()?
-            .https_or_http()
-            .enable_http1()
-            .enable_http2()
-            .build();
+        // Each attempt is recorded as its own exchange under one correlation id
+        let retry = RetryPolicy::new(&config.upstream.retry);
+        let mut attempt = 1;
+        let mut prepared = received;
 
-        let client: hyper_util::client::legacy::Client<_, Full<Bytes>> =
-            hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
-                .build(https);
+        loop {
+            let exchange = Exchange {
+                request_id: Uuid::new_v4(),
+                session_id: session_id.clone(),
+                correlation_id: correlation_id.clone(),
+                origin: origin.clone(),
+            };
+            let attempt_number = retry.enabled().then_some(attempt);
+
+            // Exchanges with a logged request stay registered until their
+            // response is logged, so shutdown can wait for them
+            let mut guard = None;
+
+            // Log request
+            if config.recording.include_bodies {
+                if let Some(decision) = early_capture.filter(|c| *c != Capture::Skip) {
+                    guard = Some(ctx.in_flight.register(exchange.request_id, &session_id, &correlation_id, &origin));
+                    Self::log_request(&ctx, &exchange, &request, attempt_number, decision).await;
+                }
+            }
 
-        // Rebuild request with collected body
-        let mut new_req = Request::builder()
-            .method(parts.method)
-            .uri(uri.clone());
+            // Start timing
+            let start = Instant::now();
+            let proxy_ms = start.duration_since(prepared).as_millis() as u64;
+            let deadline = Deadline::start(&config.upstream);
 
-        for (name, value) in parts.headers.iter() {
-            new_req = new_req.header(name, value);
-        }
+            // Rebuild request with collected body
+            let mut new_req = Request::builder()
+                .method(parts.method.clone())
+                .uri(uri.clone());
 
-        let new_req = new_req
-            .body(Full::new(body_bytes.clone()))?;
+            for (name, value) in parts.headers.iter() {
+                new_req = new_req.header(name, value);
+            }
 
-        // Send request
-        let resp = client.request(new_req).await
-            .map_err(|e| {
-                tracing::error!("Failed to forward request to {}: {:?}", uri, e);
-                e
-            })?;
+            let mut new_req = new_req
+                .body(Full::new(body_bytes.clone()))?;
+            // Replaces any credentials the client sent, which were not for this hop
+            if let Some(auth) = upstream::forward_authorization(config.upstream_proxy.as_ref(), &uri) {
+                new_req.headers_mut().insert(hyper::header::PROXY_AUTHORIZATION, auth.parse()?);
+            }
 
-        // Calculate duration
-        let duration_ms = start.elapsed().as_millis() as u64;
+            // Send request
+            let sent: Result<_, BoxError> = match deadline.run(ctx.client.request(new_req)).await {
+                Ok(result) => result.map_err(Into::into),
+                Err(timeout) => Err(timeout.into()),
+            };
+
+            // With status filters, record the request now that the status is
+            // known (for failures, the status we answer with)
+            let capture = match early_capture {
+                Some(decision) => decision,
+                None => {
+                    let status = match &sent {
+                        Ok(resp) => resp.status().as_u16(),
+                        Err(e) => upstream::classify_error(e.as_ref()).status(),
+                    };
+                    let decision = ctx.capture.decide_response(method.as_str(), uri.path(), status);
+                    if config.recording.include_bodies && decision != Capture::Skip {
+                        guard = Some(ctx.in_flight.register(exchange.request_id, &session_id, &correlation_id, &origin));
+                        Self::log_request(&ctx, &exchange, &request, attempt_number, decision).await;
+                    }
+                    decision
+                }
+            };
+            let record = config.recording.include_bodies && capture != Capture::Skip;
+
+            let outcome = match sent {
+                Ok(resp) => {
+                    let connection = upstream::connection_info(&resp);
+                    let connect_timing = connection.and_then(|c| c.timing);
+                    let connection_reused = connection.map(|c| c.reused);
+                    let timing = ExchangeTiming {
+                        client_tls_ms,
+                        proxy_ms,
+                        upstream_dns_ms: connect_timing.map(|t| t.dns_ms),
+                        upstream_connect_ms: connect_timing.map(|t| t.connect_ms),
+                        upstream_tls_ms: connect_timing.and_then(|t| t.tls_ms),
+                    };
+
+                    let (resp_parts, resp_body) = resp.into_parts();
+                    if !record {
+                        tracing::debug!("Not recording {} {} ({})", method, uri, resp_parts.status);
+                    }
 
-        let (resp_parts, resp_body) = resp.into_parts();
+                    // Server-sent events are forwarded chunk by chunk so the client sees
+                    // tokens as they are generated; everything else is buffered as before
+                    let is_event_stream = resp_parts
+                        .headers
+                        .get("content-type")
+                        .and_then(|v| v.to_str().ok())
+                        .is_some_and(|ct| ct.starts_with("text/event-stream"));
+
+                    if is_event_stream {
+                        tracing::debug!("Streaming event-stream response for {}", uri);
+
+                        let recorder = record.then(|| ResponseRecorder {
+                            ctx: ctx.clone(),
+                            exchange: exchange.clone(),
+                            status: resp_parts.status,
+                            headers: resp_parts.headers.clone(),
+                            buffer: Vec::new(),
+                            start,
+                            ttfb_ms: None,
+                            connection_reused,
+                            timing,
+                            capture,
+                            in_flight: guard.take(),
+                        });
+
+                        let body = RecordingBody::new(resp_body, recorder, deadline).boxed_unsync();
+                        return Ok(Self::rebuild_response(&resp_parts, body)?);
+                    }
 
-        // Collect response body
-        let resp_body_bytes = resp_body
-            .collect()
-            .await?
-            .to_bytes();
+                    // Collect response body, noting when the first bytes arrive
+                    Self::collect_body(resp_body, start, deadline)
+                        .await
+                        .map(|(bytes, ttfb_ms)| (resp_parts, bytes, ttfb_ms, connection_reused, timing))
+                }
+                Err(e) => Err(e),
+            };
+
+            let (resp_parts, resp_body_bytes, ttfb_ms, connection_reused, timing) = match outcome {
+                Ok(response) => response,
+                Err(e) => {
+                    let (kind, message) = Self::log_upstream_error(
+                        &ctx,
+                        &exchange,
+                        e.as_ref(),
+                        &uri,
+                        start.elapsed().as_millis() as u64,
+                        guard.is_some(),
+                    )
+                    .await;
+                    drop(guard);
+
+                    if let Some(delay) = retry.after_error(&method, kind, attempt) {
+                        tracing::info!(
+                            "Retrying {} {} in {}ms after attempt {} failed ({})",
+                            method,
+                            uri,
+                            delay.as_millis(),
+                            attempt,
+                            kind
+                        );
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                        prepared = Instant::now();
+                        continue;
+                    }
+                    return Ok(Self::upstream_error_response(kind, &message, &uri, &exchange.request_id));
+                }
+            };
+
+            // Calculate duration
+            let duration_ms = start.elapsed().as_millis() as u64;
+
+            // Log response
+            if record {
+                let response = UpstreamResponse {
+                    status: resp_parts.status,
+                    headers: &resp_parts.headers,
+                    body: &resp_body_bytes,
+                    duration_ms,
+                    ttfb_ms,
+                    connection_reused,
+                    timing,
+                };
+                Self::log_response(&ctx, &exchange, response, capture).await;
+            }
+            drop(guard);
+
+            if let Some(delay) = retry.after_response(resp_parts.status, &resp_parts.headers, attempt) {
+                tracing::info!(
+                    "Retrying {} {} in {}ms after attempt {} returned {}",
+                    method,
+                    uri,
+                    delay.as_millis(),
+                    attempt,
+                    resp_parts.status.as_u16()
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                prepared = Instant::now();
+                continue;
+            }
 
-        // Log response
-        if config.recording.include_bodies {
-            Self::log_response(
-                &request_id,
-                &session_id.to_string(),
-                &correlation_id,
-                resp_parts.status,
-                &resp_parts.headers,
-                &resp_body_bytes,
-                duration_ms,
-                &config,
-                &log_writer,
-            )
-            .await;
+            return Ok(Self::rebuild_response(&resp_parts, full(resp_body_bytes))?);
         }
+    }
 
-        // Rebuild response
-        let mut response = Response::builder().status(resp_parts.status);
+    /// Read a whole response body within the deadline
+    ///
+    /// Returns the body and the time to its first byte.
+    async fn collect_body(
+        mut body: Incoming,
+        start: Instant,
+        deadline: Deadline,
+    ) -> Result<(Bytes, Option<u64>), BoxError> {
+        let mut ttfb_ms = None;
+        let mut collected = Vec::new();
+        while let Some(frame) = deadline.run(body.frame()).await? {
+            if let Ok(data) = frame?.into_data() {
+                ttfb_ms.get_or_insert_with(|| start.elapsed().as_millis() as u64);
+                collected.extend_from_slice(&data);
+            }
+        }
+        Ok((Bytes::from(collected), ttfb_ms))
+    }
+
+    /// Response to the client with the upstream status and headers
+    fn rebuild_response(
+        parts: &hyper::http::response::Parts,
+        body: BoxBody,
+    ) -> Result<Response<BoxBody>, hyper::http::Error> {
+        let mut response = Response::builder().status(parts.status);
 
-        for (name, value) in resp_parts.headers.iter() {
+        for (name, value) in parts.headers.iter() {
             response = response.header(name, value);
         }
 
-        Ok(response.body(full(resp_body_bytes))?)
+        response.body(body)
     }
 
-    async fn log_request(
-        request_id: &Uuid,
-        session_id: &str,
-        correlation_id: &str,
-        method: &Method,
+    /// Trace a failed upstream exchange and record it as an error event
+    ///
+    /// The error event is only written when the request event was (`recorded`).
+    async fn log_upstream_error(
+        ctx: &ProxyContext,
+        exchange: &Exchange,
+        error: &(dyn std::error::Error + Send + Sync + 'static),
         uri: &Uri,
-        headers: &hyper::HeaderMap,
-        body: &Bytes,
-        config: &ProxyConfig,
-        log_writer: &Arc<LogWriter>,
+        duration_ms: u64,
+        recorded: bool,
+    ) -> (UpstreamErrorKind, String) {
+        let kind = upstream::classify_error(error);
+        let message = upstream::error_chain(error);
+        tracing::error!("Failed to forward request to {} ({}): {}", uri, kind, message);
+
+        if recorded {
+            let entry = LogEntry::new_proxy_error(
+                exchange.session_id.clone(),
+                exchange.correlation_id.clone(),
+                exchange.request_id,
+                kind,
+                message.clone(),
+                duration_ms,
+            )
+            .with_origin(&exchange.origin);
+            let _ = ctx.log_writer.write_async(entry).await;
+        }
+
+        (kind, message)
+    }
+
+    /// The 502/504 sent to the client when the upstream failed
+    fn upstream_error_response(
+        kind: UpstreamErrorKind,
+        message: &str,
+        uri: &Uri,
+        request_id: &Uuid,
+    ) -> Response<BoxBody> {
+        // Same shape as API errors, so clients show the message
+        let body = serde_json::json!({
+            "type": "error",
+            "error": {
+                "type": "proxy_upstream_error",
+                "kind": kind,
+                "message": format!("local-logger proxy could not reach {}: {}", uri.host().unwrap_or("upstream"), message),
+            },
+            "request_id": request_id,
+        });
+
+        Response::builder()
+            .status(kind.status())
+            .header("content-type", "application/json")
+            .body(full(body.to_string()))
+            .unwrap()
+    }
+
+    async fn log_request(
+        ctx: &ProxyContext,
+        exchange: &Exchange,
+        request: &ClientRequest<'_>,
+        attempt: Option<u32>,
+        capture: Capture,
     ) {
+        let config = &ctx.config;
+        let ClientRequest {
+            method,
+            uri,
+            version,
+            headers,
+            body,
+            ..
+        } = *request;
+
         // Extract content encoding and type
         let content_encoding = headers
             .get("content-encoding")
@@ -428,14 +986,11 @@ impl ProxyServer {
             .and_then(|v| v.to_str().ok())
             .map(|s| s.to_string());
 
-        // Convert headers to HashMap
-        let headers_map: HashMap<String, String> = headers
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string()))
-            .collect();
+        // Capture every header value, including repeats and non-UTF-8 bytes
+        let headers = Headers::from_header_map(headers);
 
         // Redact sensitive headers
-        let redacted_headers = redact_sensitive_headers(&headers_map);
+        let redacted_headers = headers.redacted();
 
         // Parse URL components
         let url_components = Self::parse_url_components(uri);
@@ -444,49 +999,97 @@ impl ProxyServer {
         let endpoint_pattern = Self::detect_endpoint_pattern(uri.path());
 
         // Extract API version
-        let api_version = Self::extract_api_version(uri, &headers_map);
-
-        // Generate curl command (using redacted headers)
-        let curl_command = Some(Self::generate_curl_command(method, uri, &redacted_headers, body));
+        let api_version = Self::extract_api_version(uri, &headers);
 
         // Process body with intelligent handling
-        let body_data = BodyData::from_bytes(
+        let mut body_data = BodyData::from_bytes(
             body,
             content_encoding,
             content_type,
             config.recording.max_body_size,
         );
 
+        // Parse Claude API request fields
+        let api_request = if endpoint_pattern.as_deref() == Some("/v1/messages") {
+            api_request::extract_api_request(&body_data, config.recording.full_tool_definitions)
+        } else {
+            None
+        };
+
+        // One timestamp for the entry and the delta encoding, so a parent is
+        // always looked up in the log file the entry lands in
+        let now = chrono::Utc::now();
+        let date = now.format("%Y-%m-%d").to_string();
+        let mut pending_turn = None;
+
+        // Metadata-only entries keep the parsed fields but neither the body
+        // nor a replay command that would embed it
+        let (body_data, curl_command) = if capture == Capture::Metadata {
+            (
+                BodyData::omitted(body.len(), body_data.original_encoding, body_data.content_type),
+                None,
+            )
+        } else {
+            if config.recording.delta_encode_requests {
+                (body_data, pending_turn) =
+                    ctx.deltas.encode(exchange.request_id, &exchange.session_id, &date, body_data);
+            }
+            let body_data = Self::offload_body(ctx, body_data).await;
+
+            // Generate curl command (using redacted headers); bodies stored
+            // out of line are read back from the log instead of repeated in it
+            let stored = matches!(
+                body_data.content,
+                BodyContent::BlobRef { .. } | BodyContent::Delta { .. }
+            )
+            .then_some((date.as_str(), &exchange.request_id));
+            let curl_command = Some(Self::generate_curl_command(method, uri, &redacted_headers, body, stored));
+
+            (body_data, curl_command)
+        };
+
         let entry = LogEntry::new_proxy_request(
-            session_id.to_string(),
-            correlation_id.to_string(),
-            *request_id,
-            method.to_string(),
-            uri.to_string(),
-            redacted_headers,
-            body_data,
-            None, // tls_handshake_ms - could be tracked in future
-            url_components,
-            curl_command,
-            endpoint_pattern,
-            api_version,
-        );
+            exchange.session_id.clone(),
+            exchange.correlation_id.clone(),
+            ProxyRequestEvent {
+                session_source: request.session_source,
+                tls_handshake_ms: request.tls_handshake_ms,
+                url_components,
+                curl_command,
+                endpoint_pattern,
+                api_request,
+                api_version,
+                http_version: Some(format!("{:?}", version)),
+                attempt,
+                ..ProxyRequestEvent::new(
+                    exchange.request_id,
+                    method.to_string(),
+                    uri.to_string(),
+                    redacted_headers,
+                    body_data,
+                )
+            },
+        )
+        .with_origin(&exchange.origin)
+        .with_timestamp(now);
 
         // Use unified LogWriter with file locking for safe concurrent writes
-        let _ = log_writer.write_async(entry).await;
+        if ctx.log_writer.write_async(entry).await.is_ok() {
+            if let Some(turn) = pending_turn {
+                ctx.deltas.remember(turn);
+            }
+        }
     }
 
     async fn log_response(
-        request_id: &Uuid,
-        session_id: &str,
-        correlation_id: &str,
-        status: StatusCode,
-        headers: &hyper::HeaderMap,
-        body: &Bytes,
-        duration_ms: u64,
-        config: &ProxyConfig,
-        log_writer: &Arc<LogWriter>,
+        ctx: &ProxyContext,
+        exchange: &Exchange,
+        response: UpstreamResponse<'_>,
+        capture: Capture,
     ) {
+        let config = &ctx.config;
+        let UpstreamResponse { headers, body, .. } = response;
+
         // Extract content encoding and type
         let content_encoding = headers
             .get("content-encoding")
@@ -498,35 +1101,99 @@ impl ProxyServer {
             .and_then(|v| v.to_str().ok())
             .map(|s| s.to_string());
 
-        // Convert headers to HashMap
-        let headers_map: HashMap<String, String> = headers
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string()))
-            .collect();
+        // Capture every header value, including repeats and non-UTF-8 bytes
+        let headers = Headers::from_header_map(headers);
 
         // Redact sensitive headers (e.g., Set-Cookie)
-        let redacted_headers = redact_sensitive_headers(&headers_map);
+        let redacted_headers = headers.redacted();
+
+        // Lift rate limit state and the upstream request id out of the headers
+        let rate_limit = rate_limit::extract_rate_limit(&headers, chrono::Utc::now());
+        let upstream_request_id = rate_limit::extract_request_id(&headers);
+
+        let is_event_stream = content_type
+            .as_deref()
+            .is_some_and(|ct| ct.starts_with("text/event-stream"));
+
+        // Process body with intelligent handling, reassembling streamed Claude
+        // messages instead of storing raw SSE text
+        let mut body_data = if is_event_stream {
+            sse::stream_body(
+                body,
+                content_encoding,
+                content_type,
+                config.recording.max_body_size,
+                config.recording.keep_sse_events,
+            )
+        } else {
+            BodyData::from_bytes(
+                body,
+                content_encoding,
+                content_type,
+                config.recording.max_body_size,
+            )
+        };
 
-        // Process body with intelligent handling
-        let body_data = BodyData::from_bytes(
-            body,
-            content_encoding,
-            content_type,
-            config.recording.max_body_size,
-        );
+        // Extract token usage and price it
+        let usage = usage::extract_usage(&body_data).map(|(model, tokens)| UsageRecord {
+            cost_usd: model
+                .as_deref()
+                .and_then(|m| config.pricing.cost_usd(m, &tokens)),
+            model,
+            tokens,
+        });
+
+        // Metadata-only entries keep the usage but not the body
+        if capture == Capture::Metadata {
+            body_data = BodyData::omitted(
+                body_data.size_bytes,
+                body_data.original_encoding,
+                body_data.content_type,
+            );
+        }
+        let body_data = Self::offload_body(ctx, body_data).await;
 
         let entry = LogEntry::new_proxy_response(
-            session_id.to_string(),
-            correlation_id.to_string(),
-            *request_id,
-            status.as_u16(),
-            redacted_headers,
-            body_data,
-            duration_ms,
-        );
+            exchange.session_id.clone(),
+            exchange.correlation_id.clone(),
+            ProxyResponseEvent {
+                ttfb_ms: response.ttfb_ms,
+                usage,
+                rate_limit,
+                upstream_request_id,
+                connection_reused: response.connection_reused,
+                timing: Some(response.timing),
+                ..ProxyResponseEvent::new(
+                    exchange.request_id,
+                    response.status.as_u16(),
+                    redacted_headers,
+                    body_data,
+                    response.duration_ms,
+                )
+            },
+        )
+        .with_origin(&exchange.origin);
 
         // Use unified LogWriter with file locking for safe concurrent writes
-        let _ = log_writer.write_async(entry).await;
+        let _ = ctx.log_writer.write_async(entry).await;
+    }
+
+    /// Move a large body to the blob store when a threshold is configured
+    ///
+    /// Hashing and writing the blob run on the blocking pool, off the
+    /// connection's task.
+    async fn offload_body(ctx: &ProxyContext, body: BodyData) -> BodyData {
+        let Some(threshold) = ctx.config.recording.blob_threshold_bytes else {
+            return body;
+        };
+        let omitted = BodyData::omitted(body.size_bytes, body.original_encoding.clone(), body.content_type.clone());
+        let store = BlobStore::new(ctx.log_writer.logs_dir());
+        tokio::task::spawn_blocking(move || store.offload(body, threshold))
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Failed to store body blob: {}", e);
+                omitted
+            })
     }
 
     /// Parse URI into URL components
@@ -592,7 +1259,7 @@ impl ProxyServer {
     }
 
     /// Extract API version from URL or headers
-    fn extract_api_version(uri: &Uri, headers: &HashMap<String, String>) -> Option<String> {
+    fn extract_api_version(uri: &Uri, headers: &Headers) -> Option<String> {
         // Try path first
         if let Some(path) = uri.path().split('/').find(|s| s.starts_with('v') && s[1..].chars().all(|c| c.is_numeric() || c == '.')) {
             return Some(path.to_string());
@@ -600,36 +1267,56 @@ impl ProxyServer {
 
         // Try headers
         if let Some(version) = headers.get("anthropic-version") {
-            return Some(version.clone());
+            return Some(version.to_string());
         }
         if let Some(version) = headers.get("api-version") {
-            return Some(version.clone());
+            return Some(version.to_string());
         }
 
         None
     }
 
     /// Generate curl command for replaying the request
+    ///
+    /// `stored` is the log date and request id of a body kept out of line,
+    /// which `local-logger request-body` prints back in full.
     fn generate_curl_command(
         method: &Method,
         uri: &Uri,
-        headers: &HashMap<String, String>,
+        headers: &Headers,
         body: &Bytes,
+        stored: Option<(&str, &Uuid)>,
     ) -> String {
-        let mut cmd = format!("curl -X {} '{}'", method, uri);
+        let mut cmd = match stored {
+            Some((date, id)) if !body.is_empty() => {
+                format!("local-logger request-body --date {} {} | \\\n  curl -X {} '{}'", date, id, method, uri)
+            }
+            _ => format!("curl -X {} '{}'", method, uri),
+        };
 
-        // Add headers (sensitive ones already redacted in headers map)
-        for (key, value) in headers {
+        // Add headers (sensitive ones already redacted), each value of a
+        // repeated header separately
+        for (key, value) in headers.iter() {
             // Skip headers that curl adds automatically
-            if key.to_lowercase() == "host" || key.to_lowercase() == "content-length" {
+            if key.eq_ignore_ascii_case("host") || key.eq_ignore_ascii_case("content-length") {
                 continue;
             }
-            cmd.push_str(&format!(" \\\n  -H '{}: {}'", key, value));
+            let quoted = match value {
+                HeaderValueData::Text(text) => format!("'{}: {}'", key, text.replace('\'', "'\\''")),
+                // ANSI-C quoting reproduces the exact bytes
+                HeaderValueData::Binary { .. } => {
+                    let escaped: String = value.to_bytes().iter().map(|b| format!("\\x{:02x}", b)).collect();
+                    format!("$'{}: {}'", key, escaped)
+                }
+            };
+            cmd.push_str(&format!(" \\\n  -H {}", quoted));
         }
 
         // Add body if present
         if !body.is_empty() {
-            if let Ok(body_str) = std::str::from_utf8(body) {
+            if stored.is_some() {
+                cmd.push_str(" \\\n  --data-binary @-");
+            } else if let Ok(body_str) = std::str::from_utf8(body) {
                 // Escape single quotes in JSON
                 let escaped_body = body_str.replace('\'', "'\\''");
                 cmd.push_str(&format!(" \\\n  -d '{}'", escaped_body));
@@ -641,3 +1328,70 @@ impl ProxyServer {
         cmd
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::LogEvent;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_mitm_serves_http2() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = ProxyConfig::default();
+        config.tls.cert_dir = temp_dir.path().join("certs");
+        let log_writer = Arc::new(LogWriter::new(temp_dir.path().join("logs")).unwrap());
+        let ctx = Arc::new(ProxyContext::new(config, log_writer.clone()).unwrap());
+
+        // Nothing listens on port 1, so the upstream answer is a 502
+        let (client_io, proxy_io) = tokio::io::duplex(64 * 1024);
+        let tunnel = tokio::spawn(ProxyServer::mitm_tunnel(
+            TokioIo::new(proxy_io),
+            "localhost:1".to_string(),
+            ctx,
+            Origin::run(Uuid::new_v4()),
+        ));
+
+        // Client trusting the proxy's CA and preferring h2
+        let mut roots = rustls::RootCertStore::empty();
+        let ca_pem = std::fs::read(temp_dir.path().join("certs").join("ca.pem")).unwrap();
+        for cert in rustls_pemfile::certs(&mut ca_pem.as_slice()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let mut tls_config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        tls_config.alpn_protocols = vec![b"h2".to_vec()];
+        let tls = tokio_rustls::TlsConnector::from(Arc::new(tls_config))
+            .connect("localhost".try_into().unwrap(), client_io)
+            .await
+            .unwrap();
+        assert_eq!(tls.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+        let (mut sender, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(tls))
+                .await
+                .unwrap();
+        tokio::spawn(connection);
+        let request = Request::post("https://localhost:1/v1/messages")
+            .body(Full::new(Bytes::from_static(br#"{"messages":[]}"#)))
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        assert_eq!(response.version(), Version::HTTP_2);
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        tunnel.abort();
+
+        let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        let log = std::fs::read_to_string(log_writer.get_log_file_path(&date)).unwrap();
+        let request = log
+            .lines()
+            .filter_map(|line| LogEntry::parse_any_version(line).ok())
+            .find_map(|entry| match entry.event {
+                LogEvent::ProxyRequest(req) => Some(req),
+                _ => None,
+            })
+            .expect("request was logged");
+        assert_eq!(request.http_version.as_deref(), Some("HTTP/2.0"));
+        assert_eq!(request.uri, "https://localhost:1/v1/messages");
+    }
+}
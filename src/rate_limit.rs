@@ -0,0 +1,320 @@
+//! Rate limit headers and daily headroom reports
+//!
+//! Claude API responses report the remaining request and token budget in
+//! `anthropic-ratelimit-<kind>-{limit,remaining,reset}` headers, ask for a
+//! pause with `retry-after`, and identify themselves with `request-id`. This
+//! module lifts those headers into typed response fields and summarizes, for
+//! one day, how close the traffic came to each limit.
+
+use crate::schema::{Headers, LogEntry, LogEvent, RateLimit, RateLimitWindow};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Statuses the API answers with when it sheds load
+const THROTTLED_STATUSES: &[u16] = &[429, 529];
+
+/// Upstream request id, as quoted in API support tickets
+pub fn extract_request_id(headers: &Headers) -> Option<String> {
+    headers
+        .get("request-id")
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+/// Parse the rate limit headers of a response received at `now`
+///
+/// Returns None when the response carries none of them.
+pub fn extract_rate_limit(headers: &Headers, now: DateTime<Utc>) -> Option<RateLimit> {
+    let rate_limit = RateLimit {
+        requests: window(headers, "requests"),
+        tokens: window(headers, "tokens"),
+        input_tokens: window(headers, "input-tokens"),
+        output_tokens: window(headers, "output-tokens"),
+        retry_after_secs: headers
+            .get("retry-after")
+            .and_then(|value| parse_retry_after(value, now))
+            .map(|delay| delay.as_secs()),
+    };
+    (rate_limit != RateLimit::default()).then_some(rate_limit)
+}
+
+/// Parse a `retry-after` value given as delay seconds or an HTTP date
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - now).to_std().unwrap_or_default())
+}
+
+fn window(headers: &Headers, kind: &str) -> Option<RateLimitWindow> {
+    let header = |field: &str| headers.get(&format!("anthropic-ratelimit-{}-{}", kind, field));
+    let window = RateLimitWindow {
+        limit: header("limit").and_then(|v| v.trim().parse().ok()),
+        remaining: header("remaining").and_then(|v| v.trim().parse().ok()),
+        reset: header("reset")
+            .and_then(|v| DateTime::parse_from_rfc3339(v.trim()).ok())
+            .map(|reset| reset.with_timezone(&Utc)),
+    };
+    (window != RateLimitWindow::default()).then_some(window)
+}
+
+/// The moment a limit had the least headroom
+#[derive(Debug, Clone, Serialize)]
+pub struct LowestHeadroom {
+    pub remaining: u64,
+    pub limit: Option<u64>,
+    pub at: DateTime<Utc>,
+    pub upstream_request_id: Option<String>,
+}
+
+impl LowestHeadroom {
+    /// Whether this reading leaves no more room than `other`. Shares of the
+    /// limit are compared when both limits are known, raw counts otherwise.
+    fn at_most(&self, other: &LowestHeadroom) -> bool {
+        match (self.limit.filter(|l| *l > 0), other.limit.filter(|l| *l > 0)) {
+            (Some(limit), Some(other_limit)) => {
+                self.remaining as f64 / limit as f64 <= other.remaining as f64 / other_limit as f64
+            }
+            _ => self.remaining <= other.remaining,
+        }
+    }
+}
+
+/// A response the API rejected for load reasons
+#[derive(Debug, Clone, Serialize)]
+pub struct ThrottledResponse {
+    pub at: DateTime<Utc>,
+    pub status: u16,
+    pub retry_after_secs: Option<u64>,
+    pub upstream_request_id: Option<String>,
+}
+
+/// Rate limit headroom over one log file
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RateLimitSummary {
+    /// Responses that reported rate limit headers
+    pub responses: u64,
+    /// Least headroom seen per limit ("requests", "tokens", ...)
+    pub lowest: BTreeMap<String, LowestHeadroom>,
+    /// Throttled or overloaded responses, oldest first
+    pub throttled: Vec<ThrottledResponse>,
+}
+
+impl RateLimitSummary {
+    fn observe(&mut self, kind: &str, window: &Option<RateLimitWindow>, at: DateTime<Utc>, upstream_request_id: &Option<String>) {
+        let Some(remaining) = window.as_ref().and_then(|w| w.remaining) else {
+            return;
+        };
+        let candidate = LowestHeadroom {
+            remaining,
+            limit: window.as_ref().and_then(|w| w.limit),
+            at,
+            upstream_request_id: upstream_request_id.clone(),
+        };
+        match self.lowest.get(kind) {
+            Some(lowest) if lowest.at_most(&candidate) => {}
+            _ => {
+                self.lowest.insert(kind.to_string(), candidate);
+            }
+        }
+    }
+}
+
+impl fmt::Display for RateLimitSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} response(s) reported rate limits", self.responses)?;
+
+        if !self.lowest.is_empty() {
+            writeln!(f, "\nLeast headroom:")?;
+            for (kind, lowest) in &self.lowest {
+                write!(f, "  {}: {} left", kind, lowest.remaining)?;
+                if let Some(limit) = lowest.limit.filter(|l| *l > 0) {
+                    let used = 100.0 * (1.0 - lowest.remaining as f64 / limit as f64);
+                    write!(f, " of {} ({:.0}% used)", limit, used)?;
+                }
+                write!(f, " at {}", lowest.at.format("%H:%M:%S"))?;
+                if let Some(id) = &lowest.upstream_request_id {
+                    write!(f, " ({})", id)?;
+                }
+                writeln!(f)?;
+            }
+        }
+
+        if !self.throttled.is_empty() {
+            writeln!(f, "\nThrottled responses:")?;
+            for throttled in &self.throttled {
+                write!(f, "  {} {}", throttled.at.format("%H:%M:%S"), throttled.status)?;
+                if let Some(secs) = throttled.retry_after_secs {
+                    write!(f, " retry after {}s", secs)?;
+                }
+                if let Some(id) = &throttled.upstream_request_id {
+                    write!(f, " ({})", id)?;
+                }
+                writeln!(f)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Summarize the rate limit state recorded on proxy response events
+pub fn summarize<'a>(entries: impl IntoIterator<Item = &'a LogEntry>) -> RateLimitSummary {
+    let mut summary = RateLimitSummary::default();
+
+    for entry in entries {
+        let LogEvent::ProxyResponse(resp) = &entry.event else {
+            continue;
+        };
+
+        if THROTTLED_STATUSES.contains(&resp.status) {
+            summary.throttled.push(ThrottledResponse {
+                at: entry.timestamp,
+                status: resp.status,
+                retry_after_secs: resp.rate_limit.as_ref().and_then(|r| r.retry_after_secs),
+                upstream_request_id: resp.upstream_request_id.clone(),
+            });
+        }
+
+        let Some(rate_limit) = &resp.rate_limit else {
+            continue;
+        };
+        summary.responses += 1;
+        let id = &resp.upstream_request_id;
+        summary.observe("requests", &rate_limit.requests, entry.timestamp, id);
+        summary.observe("tokens", &rate_limit.tokens, entry.timestamp, id);
+        summary.observe("input_tokens", &rate_limit.input_tokens, entry.timestamp, id);
+        summary.observe("output_tokens", &rate_limit.output_tokens, entry.timestamp, id);
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{BodyData, HeaderValueData, ProxyResponseEvent};
+    use chrono::TimeZone;
+
+    fn headers(pairs: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::default();
+        for (name, value) in pairs {
+            headers.push(*name, HeaderValueData::Text(value.to_string()));
+        }
+        headers
+    }
+
+    fn response_entry(status: u16, headers: Headers) -> LogEntry {
+        let now = Utc::now();
+        LogEntry::new_proxy_response(
+            "session".to_string(),
+            "correlation".to_string(),
+            ProxyResponseEvent {
+                rate_limit: extract_rate_limit(&headers, now),
+                upstream_request_id: extract_request_id(&headers),
+                ..ProxyResponseEvent::new(
+                    uuid::Uuid::new_v4(),
+                    status,
+                    headers.clone(),
+                    BodyData::from_bytes(b"", None, None, 1024),
+                    10,
+                )
+            },
+        )
+    }
+
+    #[test]
+    fn test_extract_rate_limit_headers() {
+        let now = Utc.with_ymd_and_hms(2025, 1, 1, 12, 0, 0).unwrap();
+        let headers = headers(&[
+            ("request-id", "req_011CTest"),
+            ("anthropic-ratelimit-requests-limit", "50"),
+            ("anthropic-ratelimit-requests-remaining", "49"),
+            ("anthropic-ratelimit-requests-reset", "2025-01-01T12:00:01Z"),
+            ("anthropic-ratelimit-input-tokens-remaining", "39000"),
+            ("retry-after", "Wed, 01 Jan 2025 12:00:30 GMT"),
+        ]);
+
+        let rate_limit = extract_rate_limit(&headers, now).unwrap();
+        let requests = rate_limit.requests.unwrap();
+        assert_eq!(requests.limit, Some(50));
+        assert_eq!(requests.remaining, Some(49));
+        assert_eq!(requests.reset, Some(Utc.with_ymd_and_hms(2025, 1, 1, 12, 0, 1).unwrap()));
+        assert_eq!(rate_limit.input_tokens.unwrap().remaining, Some(39000));
+        assert!(rate_limit.tokens.is_none());
+        assert_eq!(rate_limit.retry_after_secs, Some(30));
+
+        assert_eq!(extract_request_id(&headers).as_deref(), Some("req_011CTest"));
+    }
+
+    #[test]
+    fn test_responses_without_rate_limit_headers() {
+        let headers = headers(&[("content-type", "application/json")]);
+        assert!(extract_rate_limit(&headers, Utc::now()).is_none());
+        assert!(extract_request_id(&headers).is_none());
+    }
+
+    #[test]
+    fn test_summarize_finds_lowest_headroom_and_throttling() {
+        let entries = vec![
+            response_entry(
+                200,
+                headers(&[
+                    ("request-id", "req_a"),
+                    ("anthropic-ratelimit-requests-limit", "50"),
+                    ("anthropic-ratelimit-requests-remaining", "40"),
+                ]),
+            ),
+            response_entry(
+                200,
+                headers(&[
+                    ("request-id", "req_b"),
+                    ("anthropic-ratelimit-requests-limit", "50"),
+                    ("anthropic-ratelimit-requests-remaining", "2"),
+                ]),
+            ),
+            response_entry(429, headers(&[("request-id", "req_c"), ("retry-after", "15")])),
+            LogEntry::new_mcp("session".to_string(), "INFO".to_string(), "ignored".to_string()),
+        ];
+
+        let summary = summarize(&entries);
+        assert_eq!(summary.responses, 3);
+
+        let requests = &summary.lowest["requests"];
+        assert_eq!(requests.remaining, 2);
+        assert_eq!(requests.upstream_request_id.as_deref(), Some("req_b"));
+
+        assert_eq!(summary.throttled.len(), 1);
+        assert_eq!(summary.throttled[0].retry_after_secs, Some(15));
+        assert_eq!(summary.throttled[0].upstream_request_id.as_deref(), Some("req_c"));
+
+        let text = summary.to_string();
+        assert!(text.contains("requests: 2 left of 50 (96% used)"));
+        assert!(text.contains("429 retry after 15s (req_c)"));
+    }
+
+    #[test]
+    fn test_unknown_limit_not_compared_against_fraction() {
+        let entries = vec![
+            response_entry(
+                200,
+                headers(&[
+                    ("request-id", "req_a"),
+                    ("anthropic-ratelimit-tokens-limit", "100000"),
+                    ("anthropic-ratelimit-tokens-remaining", "90000"),
+                ]),
+            ),
+            response_entry(200, headers(&[("request-id", "req_b"), ("anthropic-ratelimit-tokens-remaining", "5")])),
+        ];
+
+        let tokens = &summarize(&entries).lowest["tokens"];
+        assert_eq!(tokens.remaining, 5);
+        assert_eq!(tokens.upstream_request_id.as_deref(), Some("req_b"));
+    }
+}
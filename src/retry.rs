@@ -0,0 +1,162 @@
+//! Retry policy for forwarded requests
+//!
+//! Two kinds of failure are retried once `max_retries` is set:
+//! - connection failures and timeouts, for idempotent methods only, since a
+//!   request that may have reached the API is not safe to send twice
+//! - responses with a configured status (429 rate limited, 529 overloaded by
+//!   default), for any method, waiting as long as `retry-after` asks
+//!
+//! Every attempt is recorded as its own exchange under the same correlation id.
+
+use crate::proxy_config::RetryConfig;
+use crate::rate_limit;
+use crate::schema::UpstreamErrorKind;
+use chrono::{DateTime, Utc};
+use hyper::{HeaderMap, Method, StatusCode};
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    config: RetryConfig,
+}
+
+impl RetryPolicy {
+    pub fn new(config: &RetryConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    /// Whether retries are configured at all
+    pub fn enabled(&self) -> bool {
+        self.config.max_retries > 0
+    }
+
+    /// Delay before retrying a request that failed without a response
+    ///
+    /// `attempt` is the number of the attempt that just failed, from 1.
+    pub fn after_error(&self, method: &Method, kind: UpstreamErrorKind, attempt: u32) -> Option<Duration> {
+        let retryable = matches!(
+            kind,
+            UpstreamErrorKind::Dns
+                | UpstreamErrorKind::ConnectRefused
+                | UpstreamErrorKind::Connect
+                | UpstreamErrorKind::Timeout
+                | UpstreamErrorKind::Reset
+        );
+        (self.has_attempts_left(attempt) && retryable && method.is_idempotent())
+            .then(|| self.backoff(attempt))
+    }
+
+    /// Delay before retrying a request answered with `status`
+    ///
+    /// Returns None when the status is not retried, or `retry-after` asks for
+    /// longer than `max_backoff_secs`.
+    pub fn after_response(&self, status: StatusCode, headers: &HeaderMap, attempt: u32) -> Option<Duration> {
+        if !self.has_attempts_left(attempt) || !self.config.retry_statuses.contains(&status.as_u16()) {
+            return None;
+        }
+
+        let delay = retry_after(headers, Utc::now()).unwrap_or_else(|| self.backoff(attempt));
+        (delay <= self.max_backoff()).then_some(delay)
+    }
+
+    fn has_attempts_left(&self, attempt: u32) -> bool {
+        attempt <= self.config.max_retries
+    }
+
+    /// Exponential backoff: the initial delay doubled per earlier retry
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = Duration::from_millis(self.config.initial_backoff_ms)
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        delay.min(self.max_backoff())
+    }
+
+    fn max_backoff(&self) -> Duration {
+        Duration::from_secs(self.config.max_backoff_secs)
+    }
+}
+
+/// Parse `retry-after` as delay seconds or an HTTP date
+fn retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let value = headers.get(hyper::header::RETRY_AFTER)?.to_str().ok()?;
+    rate_limit::parse_retry_after(value, now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy::new(&RetryConfig {
+            max_retries,
+            ..Default::default()
+        })
+    }
+
+    fn headers(retry_after: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(hyper::header::RETRY_AFTER, retry_after.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        let policy = policy(0);
+        assert!(!policy.enabled());
+        assert_eq!(policy.after_error(&Method::GET, UpstreamErrorKind::Reset, 1), None);
+        assert_eq!(policy.after_response(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new(), 1), None);
+    }
+
+    #[test]
+    fn test_errors_retried_for_idempotent_methods_only() {
+        let policy = policy(2);
+
+        assert_eq!(
+            policy.after_error(&Method::GET, UpstreamErrorKind::ConnectRefused, 1),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            policy.after_error(&Method::GET, UpstreamErrorKind::Timeout, 2),
+            Some(Duration::from_millis(1000))
+        );
+        // Out of retries
+        assert_eq!(policy.after_error(&Method::GET, UpstreamErrorKind::Timeout, 3), None);
+        // Not idempotent, or not transient
+        assert_eq!(policy.after_error(&Method::POST, UpstreamErrorKind::Reset, 1), None);
+        assert_eq!(policy.after_error(&Method::GET, UpstreamErrorKind::Tls, 1), None);
+    }
+
+    #[test]
+    fn test_overloaded_responses_honour_retry_after() {
+        let policy = policy(1);
+
+        assert_eq!(
+            policy.after_response(StatusCode::TOO_MANY_REQUESTS, &headers("7"), 1),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            policy.after_response(StatusCode::from_u16(529).unwrap(), &HeaderMap::new(), 1),
+            Some(Duration::from_millis(500))
+        );
+        // Longer than max_backoff_secs: give the response to the client
+        assert_eq!(policy.after_response(StatusCode::TOO_MANY_REQUESTS, &headers("120"), 1), None);
+        assert_eq!(policy.after_response(StatusCode::INTERNAL_SERVER_ERROR, &HeaderMap::new(), 1), None);
+        assert_eq!(policy.after_response(StatusCode::TOO_MANY_REQUESTS, &headers("1"), 2), None);
+    }
+
+    #[test]
+    fn test_retry_after_http_date() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z").unwrap().with_timezone(&Utc);
+
+        assert_eq!(
+            retry_after(&headers("Wed, 21 Oct 2015 07:28:05 GMT"), now),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            retry_after(&headers("Wed, 21 Oct 2015 07:27:00 GMT"), now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after(&headers("soon"), now), None);
+    }
+}
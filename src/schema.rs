@@ -1,15 +1,20 @@
 //! Strongly-typed log schema for local-logger
 //!
 //! This module defines the complete type hierarchy for all log events.
-//! The schema is versioned to enable future migrations.
+//! The schema is versioned; entries written with older versions are upgraded
+//! by [`crate::migration`] when read.
 
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use uuid::Uuid;
 
 /// Current schema version
-pub const SCHEMA_VERSION: u32 = 1;
+///
+/// Bump it together with a new upgrader in [`crate::migration`] whenever
+/// entries of the previous version would no longer deserialize.
+pub const SCHEMA_VERSION: u32 = 2;
 
 /// Sensitive headers that should be redacted in logs
 pub const SENSITIVE_HEADERS: &[&str] = &[
@@ -26,7 +31,7 @@ pub const SENSITIVE_HEADERS: &[&str] = &[
 ];
 
 /// Root log entry structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct LogEntry {
     /// Schema version for migration support
     pub schema_version: u32,
@@ -38,13 +43,55 @@ pub struct LogEntry {
     pub session_id: String,
     /// Correlation ID for linking related events (e.g., request/response pairs)
     pub correlation_id: String,
+    /// Proxy process and client connection that produced the entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
     /// The actual log event
     pub event: LogEvent,
 }
 
+/// Where a proxy entry comes from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Origin {
+    /// Random id chosen each time the proxy starts
+    pub proxy_run_id: Uuid,
+    /// Process id of the proxy itself, the same on every entry of a run
+    ///
+    /// The client's process is not known: connections only carry its address.
+    pub proxy_pid: u32,
+    /// Sequence number of the client connection within the run, from 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection_id: Option<u64>,
+    /// Client address of the connection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_addr: Option<String>,
+}
+
+impl Origin {
+    /// Origin of entries not tied to a client connection
+    pub fn run(proxy_run_id: Uuid) -> Self {
+        Self {
+            proxy_run_id,
+            proxy_pid: std::process::id(),
+            connection_id: None,
+            peer_addr: None,
+        }
+    }
+
+    /// Origin of entries about one client connection
+    pub fn connection(&self, connection_id: u64, peer_addr: std::net::SocketAddr) -> Self {
+        Self {
+            connection_id: Some(connection_id),
+            peer_addr: Some(peer_addr.to_string()),
+            ..self.clone()
+        }
+    }
+}
+
 /// Discriminated union of all possible log event types
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
+#[allow(clippy::large_enum_variant)]
 pub enum LogEvent {
     /// MCP server log event
     Mcp(McpLogEvent),
@@ -54,12 +101,73 @@ pub enum LogEvent {
     ProxyRequest(ProxyRequestEvent),
     /// Proxy response event
     ProxyResponse(ProxyResponseEvent),
+    /// Upstream failure in place of a proxy response
+    ProxyError(ProxyErrorEvent),
     /// Proxy debug/info/error log event
     ProxyDebug(ProxyDebugEvent),
+    /// Event of a type added after this version, kept verbatim
+    #[serde(untagged)]
+    Unknown(UnknownEvent),
+}
+
+/// `type` tags of the event variants this version understands
+pub const KNOWN_EVENT_TYPES: &[&str] = &[
+    "Mcp",
+    "Hook",
+    "ProxyRequest",
+    "ProxyResponse",
+    "ProxyError",
+    "ProxyDebug",
+];
+
+/// Event written by a newer local-logger, e.g. a hook binary ahead of the proxy
+///
+/// Serializes back to exactly the fields it was read from. Known types never
+/// land here, so a malformed `ProxyRequest` is still an error.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(try_from = "RawUnknownEvent")]
+pub struct UnknownEvent {
+    /// The event's `type` tag
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// All other fields of the event
+    #[serde(flatten)]
+    pub payload: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Shape of [`UnknownEvent`] before known types are turned away
+#[derive(Deserialize, JsonSchema)]
+struct RawUnknownEvent {
+    #[serde(rename = "type")]
+    #[schemars(schema_with = "unknown_event_type")]
+    kind: String,
+    #[serde(flatten)]
+    payload: serde_json::Map<String, serde_json::Value>,
+}
+
+fn unknown_event_type(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    schemars::json_schema!({
+        "type": "string",
+        "not": { "enum": KNOWN_EVENT_TYPES },
+    })
+}
+
+impl TryFrom<RawUnknownEvent> for UnknownEvent {
+    type Error = String;
+
+    fn try_from(raw: RawUnknownEvent) -> Result<Self, Self::Error> {
+        if KNOWN_EVENT_TYPES.contains(&raw.kind.as_str()) {
+            return Err(format!("invalid {} event", raw.kind));
+        }
+        Ok(Self {
+            kind: raw.kind,
+            payload: raw.payload,
+        })
+    }
 }
 
 /// MCP server log event
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct McpLogEvent {
     /// Log level (INFO, ERROR, WARN, etc.)
     pub level: String,
@@ -68,7 +176,7 @@ pub struct McpLogEvent {
 }
 
 /// Proxy debug/info/error log event
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ProxyDebugEvent {
     /// Log level: TRACE, DEBUG, INFO, WARN, ERROR
     pub level: String,
@@ -85,7 +193,7 @@ pub struct ProxyDebugEvent {
 }
 
 /// Claude Code hook event with rich metadata
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct HookLogEvent {
     /// Event type: "PreToolUse", "PostToolUse", etc.
     pub event_type: String,
@@ -103,7 +211,7 @@ pub struct HookLogEvent {
 }
 
 /// HTTP/HTTPS proxy request event
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ProxyRequestEvent {
     /// Unique request ID for correlation
     pub id: Uuid,
@@ -112,10 +220,15 @@ pub struct ProxyRequestEvent {
     /// Full request URI
     pub uri: String,
     /// Request headers (sensitive headers redacted)
-    pub headers: HashMap<String, String>,
+    pub headers: Headers,
     /// Request body with metadata
     pub body: BodyData,
-    /// Optional TLS handshake time in milliseconds (for first request to host)
+    /// How the entry's session id was linked to a Claude Code session
+    ///
+    /// Absent when no session was found and the id is random.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_source: Option<SessionSource>,
+    /// Time of the TLS handshake with the client for the MITM tunnel carrying this request
     pub tls_handshake_ms: Option<u64>,
     /// Parsed URL components for API replay
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -126,13 +239,58 @@ pub struct ProxyRequestEvent {
     /// Detected API endpoint pattern (e.g., "/v1/messages")
     #[serde(skip_serializing_if = "Option::is_none")]
     pub endpoint_pattern: Option<String>,
+    /// Typed summary of a Claude Messages API request body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_request: Option<ApiRequest>,
     /// API version detected from URL or headers
     #[serde(skip_serializing_if = "Option::is_none")]
     pub api_version: Option<String>,
+    /// HTTP protocol version spoken by the client (e.g., "HTTP/1.1", "HTTP/2.0")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_version: Option<String>,
+    /// Attempt number, from 1, when retries are enabled; retried attempts
+    /// share the correlation ID of the first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attempt: Option<u32>,
+}
+
+impl ProxyRequestEvent {
+    /// Request event with only the request itself; the optional fields are
+    /// set with struct update syntax
+    pub fn new(id: Uuid, method: String, uri: String, headers: Headers, body: BodyData) -> Self {
+        Self {
+            id,
+            method,
+            uri,
+            headers,
+            body,
+            session_source: None,
+            tls_handshake_ms: None,
+            url_components: None,
+            curl_command: None,
+            endpoint_pattern: None,
+            api_request: None,
+            api_version: None,
+            http_version: None,
+            attempt: None,
+        }
+    }
+}
+
+/// Where a proxy request's Claude Code session id came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionSource {
+    /// The `x-claude-code-session-id` request header
+    Header,
+    /// `metadata.user_id` in the request body
+    Metadata,
+    /// The only session with recent hook events
+    HookTiming,
 }
 
 /// Parsed URL components for API replay
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct UrlComponents {
     /// URL scheme (http, https)
     pub scheme: String,
@@ -146,23 +304,234 @@ pub struct UrlComponents {
     pub query_params: HashMap<String, String>,
 }
 
+/// Fields of a Claude Messages API request, parsed from its body
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ApiRequest {
+    /// Requested model (e.g., "claude-sonnet-4-5")
+    pub model: Option<String>,
+    /// Output token limit
+    pub max_tokens: Option<u64>,
+    /// Whether the response is streamed
+    pub stream: bool,
+    /// Number of entries in `messages`
+    pub message_count: usize,
+    /// Characters of system prompt text, summed over all system blocks
+    pub system_prompt_chars: usize,
+    /// Names of the tools offered to the model, in request order
+    pub tool_names: Vec<String>,
+    /// Complete tool definitions, when configured to keep them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<serde_json::Value>>,
+    /// Extended thinking settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<ThinkingSettings>,
+}
+
+/// Extended thinking settings of a request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ThinkingSettings {
+    /// "enabled", "disabled", ...
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Token budget for thinking, when enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget_tokens: Option<u64>,
+}
+
 /// HTTP/HTTPS proxy response event
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ProxyResponseEvent {
     /// References the request ID
     pub request_id: Uuid,
     /// HTTP status code
     pub status: u16,
     /// Response headers
-    pub headers: HashMap<String, String>,
+    pub headers: Headers,
     /// Response body with metadata
     pub body: BodyData,
     /// Time from request to response in milliseconds
+    ///
+    /// For streamed responses this covers the whole stream, up to the final chunk.
     pub duration_ms: u64,
+    /// Time from request to the first response body byte in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttfb_ms: Option<u64>,
+    /// Token usage and cost reported by the Claude API
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<UsageRecord>,
+    /// Rate limit state reported in the response headers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+    /// Upstream `request-id` header, quoted in API support tickets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_request_id: Option<String>,
+    /// Whether the upstream connection had already served an earlier request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_reused: Option<bool>,
+    /// Where the time of this exchange went, phase by phase
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timing: Option<ExchangeTiming>,
+    /// Why the exchange was cut off before a response was recorded
+    ///
+    /// Set on synthetic responses written at shutdown; `status` is 0 then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aborted: Option<String>,
+}
+
+impl ProxyResponseEvent {
+    /// Response event with only the response itself; the optional fields are
+    /// set with struct update syntax
+    pub fn new(request_id: Uuid, status: u16, headers: Headers, body: BodyData, duration_ms: u64) -> Self {
+        Self {
+            request_id,
+            status,
+            headers,
+            body,
+            duration_ms,
+            ttfb_ms: None,
+            usage: None,
+            rate_limit: None,
+            upstream_request_id: None,
+            connection_reused: None,
+            timing: None,
+            aborted: None,
+        }
+    }
+}
+
+/// Rate limit state from the `anthropic-ratelimit-*` and `retry-after` headers
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RateLimit {
+    /// Requests per minute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests: Option<RateLimitWindow>,
+    /// Tokens per minute (input and output combined)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<RateLimitWindow>,
+    /// Input tokens per minute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_tokens: Option<RateLimitWindow>,
+    /// Output tokens per minute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_tokens: Option<RateLimitWindow>,
+    /// Seconds the API asked to wait before retrying
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
+}
+
+/// One rate limit: its size, what is left, and when it is replenished
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RateLimitWindow {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reset: Option<DateTime<Utc>>,
+}
+
+/// Upstream failure for a proxied request
+///
+/// Written instead of a [`ProxyResponseEvent`] when no response could be
+/// obtained from the API; the client received the synthesized `status`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ProxyErrorEvent {
+    /// References the request ID
+    pub request_id: Uuid,
+    /// What went wrong
+    pub kind: UpstreamErrorKind,
+    /// Error message, including its causes
+    pub message: String,
+    /// Time from forwarding the request until the failure, in milliseconds
+    pub duration_ms: u64,
+    /// Status returned to the client (502 or 504)
+    pub status: u16,
+}
+
+/// Classification of upstream failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamErrorKind {
+    /// The host name did not resolve
+    Dns,
+    /// The host refused the TCP connection
+    ConnectRefused,
+    /// Any other failure to establish the TCP connection
+    Connect,
+    /// TLS handshake or certificate failure
+    Tls,
+    /// The upstream did not answer in time
+    Timeout,
+    /// The connection was reset or closed mid-exchange
+    Reset,
+    /// Anything else (protocol errors, etc.)
+    Other,
+}
+
+impl UpstreamErrorKind {
+    /// Status code returned to the client for this failure
+    pub fn status(self) -> u16 {
+        match self {
+            Self::Timeout => 504,
+            _ => 502,
+        }
+    }
+}
+
+impl std::fmt::Display for UpstreamErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Dns => "dns",
+            Self::ConnectRefused => "connect_refused",
+            Self::Connect => "connect",
+            Self::Tls => "tls",
+            Self::Timeout => "timeout",
+            Self::Reset => "reset",
+            Self::Other => "other",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Per-phase timing of a proxied exchange, in milliseconds
+///
+/// Together with `ttfb_ms` and `duration_ms` this shows whether time went to
+/// the client link, the proxy itself, the network to the API, or the API.
+/// Upstream connection phases are only set on the exchange that opened the
+/// connection; requests over a reused connection skip them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ExchangeTiming {
+    /// TLS handshake with the client when the MITM tunnel was opened
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_tls_ms: Option<u64>,
+    /// Reading the request body from the client and recording it before forwarding
+    pub proxy_ms: u64,
+    /// Upstream DNS resolution
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_dns_ms: Option<u64>,
+    /// Upstream TCP connect
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_connect_ms: Option<u64>,
+    /// Upstream TLS handshake
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_tls_ms: Option<u64>,
+}
+
+/// Token usage of a single API call with its computed cost
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct UsageRecord {
+    /// Model that served the request
+    pub model: Option<String>,
+    /// Token counts
+    #[serde(flatten)]
+    pub tokens: MessageUsage,
+    /// Cost in USD according to the configured price table (None if the model has no price)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
 }
 
 /// Intelligent body data handling with metadata
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct BodyData {
     /// Original encoding (gzip, deflate, br, etc.)
     pub original_encoding: Option<String>,
@@ -179,7 +548,7 @@ pub struct BodyData {
 }
 
 /// Body content with explicit handling of different cases
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum BodyContent {
     /// Text body (UTF-8)
@@ -188,35 +557,293 @@ pub enum BodyContent {
     Binary { data: String },
     /// Truncated body with preview
     Truncated { preview: String, reason: String },
+    /// Oversized JSON body shrunk to fit while staying valid JSON
+    ///
+    /// Long strings and arrays are elided with markers noting what was left out.
+    TruncatedJson { data: String, reason: String },
     /// Decompression failed
     DecompressionFailed { error: String },
+    /// Server-sent event stream reassembled into the final message
+    EventStream {
+        message: StreamedMessage,
+        /// Raw events in arrival order (only kept when configured)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        events: Option<Vec<SseEvent>>,
+    },
+    /// Body stored in the blob store under its SHA-256 hash
+    ///
+    /// The blob holds the serialized content this reference replaced; log
+    /// readers resolve it transparently.
+    BlobRef { hash: String },
+    /// Claude request body stored as what it adds to an earlier request
+    ///
+    /// Use [`reconstruct_body`] to rebuild the full JSON body.
+    Delta {
+        /// Request whose body this one extends
+        parent_request_id: Uuid,
+        /// Leading `messages` entries shared with the parent
+        prefix_messages: usize,
+        /// `messages` entries after the shared prefix
+        messages: Vec<serde_json::Value>,
+        /// Other top-level fields whose value differs from the parent
+        #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+        changed: serde_json::Map<String, serde_json::Value>,
+        /// Top-level fields of the parent absent from this body
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        removed: Vec<String>,
+    },
+    /// Body left out because the exchange is recorded as metadata only
+    Omitted,
     /// Empty body
     Empty,
 }
 
+/// A single server-sent event
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SseEvent {
+    /// Event name from the `event:` field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    /// Event payload, parsed as JSON when possible
+    pub data: serde_json::Value,
+}
+
+/// Claude API message assembled from a streaming response
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct StreamedMessage {
+    /// Message ID (e.g., "msg_...")
+    pub id: Option<String>,
+    /// Model that generated the message
+    pub model: Option<String>,
+    /// Message role (normally "assistant")
+    pub role: Option<String>,
+    /// Content blocks in index order
+    pub content: Vec<ContentBlock>,
+    /// Why generation stopped (end_turn, tool_use, max_tokens, ...)
+    pub stop_reason: Option<String>,
+    /// Stop sequence that ended generation, if any
+    pub stop_sequence: Option<String>,
+    /// Token usage, merged from `message_start` and `message_delta`
+    pub usage: Option<MessageUsage>,
+    /// Whether the stream reached `message_stop`
+    pub complete: bool,
+    /// Error payload if the stream carried an `error` event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<serde_json::Value>,
+}
+
+/// Content block of an assembled message
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    /// Generated text
+    Text { text: String },
+    /// Tool invocation with its input JSON reassembled
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    /// Extended thinking output
+    Thinking {
+        thinking: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+    },
+    /// Any other block type, kept as-is
+    #[serde(untagged)]
+    Other(serde_json::Value),
+}
+
+/// Token usage reported by the Claude API
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MessageUsage {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+    #[serde(default)]
+    pub cache_creation_input_tokens: u64,
+    #[serde(default)]
+    pub cache_read_input_tokens: u64,
+}
+
+/// HTTP headers as received, repeated names included
+///
+/// Serialized as a list of `[name, value]` pairs. Version 1 entries stored a
+/// `{name: value}` object, which [`crate::migration`] converts. Names appear
+/// in order of first occurrence, and the values of a repeated name in the
+/// order they were sent.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Headers(Vec<(String, HeaderValueData)>);
+
+/// A header value, kept byte for byte
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum HeaderValueData {
+    /// Value that is valid UTF-8
+    Text(String),
+    /// Any other value (base64 encoded)
+    Binary { base64: String },
+}
+
+impl From<HashMap<String, String>> for Headers {
+    fn from(map: HashMap<String, String>) -> Self {
+        let mut headers: Vec<_> = map
+            .into_iter()
+            .map(|(name, value)| (name, HeaderValueData::Text(value)))
+            .collect();
+        headers.sort_by(|a, b| a.0.cmp(&b.0));
+        Self(headers)
+    }
+}
+
+impl HeaderValueData {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Self::Text(text.to_string()),
+            Err(_) => {
+                use base64::Engine;
+                Self::Binary {
+                    base64: base64::engine::general_purpose::STANDARD.encode(bytes),
+                }
+            }
+        }
+    }
+
+    /// The value as text, if it is valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Text(text) => Some(text),
+            Self::Binary { .. } => None,
+        }
+    }
+
+    /// The raw bytes of the value
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Text(text) => text.as_bytes().to_vec(),
+            Self::Binary { base64 } => {
+                use base64::Engine;
+                base64::engine::general_purpose::STANDARD
+                    .decode(base64)
+                    .unwrap_or_default()
+            }
+        }
+    }
+}
+
+impl Headers {
+    /// Capture every header of a request or response
+    pub fn from_header_map(map: &hyper::HeaderMap) -> Self {
+        Self(
+            map.iter()
+                .map(|(name, value)| (name.to_string(), HeaderValueData::from_bytes(value.as_bytes())))
+                .collect(),
+        )
+    }
+
+    /// Append a header, keeping earlier values of the same name
+    pub fn push(&mut self, name: impl Into<String>, value: HeaderValueData) {
+        self.0.push((name.into(), value));
+    }
+
+    /// First text value of the named header (case-insensitive)
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .find_map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of the named header (case-insensitive), in order
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a HeaderValueData> + 'a {
+        self.0
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &HeaderValueData)> {
+        self.0.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Copy with the values of sensitive headers redacted
+    pub fn redacted(&self) -> Self {
+        Self(
+            self.0
+                .iter()
+                .map(|(name, value)| {
+                    let redacted = redact_header_value(name, value.as_str().unwrap_or(""));
+                    (name.clone(), redacted.map_or_else(|| value.clone(), HeaderValueData::Text))
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Redacted form of a sensitive header's value, None for other headers
+fn redact_header_value(name: &str, value: &str) -> Option<String> {
+    let name = name.to_lowercase();
+    if !SENSITIVE_HEADERS.contains(&name.as_str()) {
+        return None;
+    }
+    // Preserve the auth type but redact the value
+    Some(match value.split_once(' ') {
+        Some((scheme, _)) if name == "authorization" => format!("[REDACTED:{}]", scheme),
+        _ => "[REDACTED]".to_string(),
+    })
+}
+
 /// Helper function to redact sensitive headers
 pub fn redact_sensitive_headers(headers: &HashMap<String, String>) -> HashMap<String, String> {
     headers
         .iter()
         .map(|(key, value)| {
-            let key_lower = key.to_lowercase();
-            if SENSITIVE_HEADERS.contains(&key_lower.as_str()) {
-                // Preserve the auth type but redact the value
-                let redacted_value = if key_lower == "authorization" && value.contains(' ') {
-                    let parts: Vec<&str> = value.splitn(2, ' ').collect();
-                    format!("[REDACTED:{}]", parts[0])
-                } else {
-                    "[REDACTED]".to_string()
-                };
-                (key.clone(), redacted_value)
-            } else {
-                (key.clone(), value.clone())
-            }
+            let value = redact_header_value(key, value).unwrap_or_else(|| value.clone());
+            (key.clone(), value)
         })
         .collect()
 }
 
+/// JSON Schema of a log line at [`SCHEMA_VERSION`]
+///
+/// `schema_version` is pinned to the current version, so lines that still
+/// need `local-logger migrate` do not validate.
+pub fn json_schema() -> serde_json::Value {
+    let mut schema = schemars::schema_for!(LogEntry);
+    schema.insert(
+        "title".to_string(),
+        format!("local-logger log entry, schema version {}", SCHEMA_VERSION).into(),
+    );
+    if let Some(version) = schema.pointer_mut("/properties/schema_version") {
+        *version = serde_json::json!({ "const": SCHEMA_VERSION });
+    }
+    schema.to_value()
+}
+
 impl LogEntry {
+    /// Attach the proxy process and connection the entry comes from
+    pub fn with_origin(mut self, origin: &Origin) -> Self {
+        self.origin = Some(origin.clone());
+        self
+    }
+
+    /// Stamp the entry with a time taken earlier, along with its date
+    pub fn with_timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.timestamp = timestamp;
+        self.date = timestamp.format("%Y-%m-%d").to_string();
+        self
+    }
+
     /// Create a new MCP log entry
     pub fn new_mcp(session_id: String, level: String, message: String) -> Self {
         let now = Utc::now();
@@ -226,6 +853,7 @@ impl LogEntry {
             date: now.format("%Y-%m-%d").to_string(),
             session_id: session_id.clone(),
             correlation_id: Uuid::new_v4().to_string(),
+            origin: None,
             event: LogEvent::Mcp(McpLogEvent { level, message }),
         }
     }
@@ -247,6 +875,7 @@ impl LogEntry {
             date: now.format("%Y-%m-%d").to_string(),
             session_id: session_id.clone(),
             correlation_id: Uuid::new_v4().to_string(),
+            origin: None,
             event: LogEvent::Hook(HookLogEvent {
                 event_type,
                 tool_name,
@@ -259,19 +888,40 @@ impl LogEntry {
     }
 
     /// Create a new proxy request log entry
-    pub fn new_proxy_request(
+    pub fn new_proxy_request(session_id: String, correlation_id: String, request: ProxyRequestEvent) -> Self {
+        let now = Utc::now();
+        Self {
+            schema_version: SCHEMA_VERSION,
+            timestamp: now,
+            date: now.format("%Y-%m-%d").to_string(),
+            session_id,
+            correlation_id,
+            origin: None,
+            event: LogEvent::ProxyRequest(request),
+        }
+    }
+
+    /// Create a new proxy response log entry
+    pub fn new_proxy_response(session_id: String, correlation_id: String, response: ProxyResponseEvent) -> Self {
+        let now = Utc::now();
+        Self {
+            schema_version: SCHEMA_VERSION,
+            timestamp: now,
+            date: now.format("%Y-%m-%d").to_string(),
+            session_id,
+            correlation_id,
+            origin: None,
+            event: LogEvent::ProxyResponse(response),
+        }
+    }
+
+    /// Create a synthetic response for an exchange that could not finish
+    pub fn new_proxy_aborted(
         session_id: String,
         correlation_id: String,
         request_id: Uuid,
-        method: String,
-        uri: String,
-        headers: HashMap<String, String>,
-        body: BodyData,
-        tls_handshake_ms: Option<u64>,
-        url_components: Option<UrlComponents>,
-        curl_command: Option<String>,
-        endpoint_pattern: Option<String>,
-        api_version: Option<String>,
+        duration_ms: u64,
+        reason: String,
     ) -> Self {
         let now = Utc::now();
         Self {
@@ -280,29 +930,21 @@ impl LogEntry {
             date: now.format("%Y-%m-%d").to_string(),
             session_id,
             correlation_id,
-            event: LogEvent::ProxyRequest(ProxyRequestEvent {
-                id: request_id,
-                method,
-                uri,
-                headers,
-                body,
-                tls_handshake_ms,
-                url_components,
-                curl_command,
-                endpoint_pattern,
-                api_version,
+            origin: None,
+            event: LogEvent::ProxyResponse(ProxyResponseEvent {
+                aborted: Some(reason),
+                ..ProxyResponseEvent::new(request_id, 0, Headers::default(), BodyData::omitted(0, None, None), duration_ms)
             }),
         }
     }
 
-    /// Create a new proxy response log entry
-    pub fn new_proxy_response(
+    /// Create a new proxy error log entry
+    pub fn new_proxy_error(
         session_id: String,
         correlation_id: String,
         request_id: Uuid,
-        status: u16,
-        headers: HashMap<String, String>,
-        body: BodyData,
+        kind: UpstreamErrorKind,
+        message: String,
         duration_ms: u64,
     ) -> Self {
         let now = Utc::now();
@@ -312,12 +954,13 @@ impl LogEntry {
             date: now.format("%Y-%m-%d").to_string(),
             session_id,
             correlation_id,
-            event: LogEvent::ProxyResponse(ProxyResponseEvent {
+            origin: None,
+            event: LogEvent::ProxyError(ProxyErrorEvent {
                 request_id,
-                status,
-                headers,
-                body,
+                kind,
+                message,
                 duration_ms,
+                status: kind.status(),
             }),
         }
     }
@@ -339,6 +982,7 @@ impl LogEntry {
             date: now.format("%Y-%m-%d").to_string(),
             session_id,
             correlation_id: Uuid::new_v4().to_string(),
+            origin: None,
             event: LogEvent::ProxyDebug(ProxyDebugEvent {
                 level,
                 message,
@@ -352,6 +996,22 @@ impl LogEntry {
 }
 
 impl BodyData {
+    /// Describe a body without storing it (metadata-only recording)
+    pub fn omitted(
+        size_bytes: usize,
+        content_encoding: Option<String>,
+        content_type: Option<String>,
+    ) -> Self {
+        Self {
+            original_encoding: content_encoding,
+            content_type,
+            size_bytes,
+            stored_size_bytes: 0,
+            truncated: false,
+            content: BodyContent::Omitted,
+        }
+    }
+
     /// Create body data from raw bytes with intelligent handling
     pub fn from_bytes(
         bytes: &[u8],
@@ -362,17 +1022,10 @@ impl BodyData {
         let original_size = bytes.len();
 
         // Handle compression
-        let (decompressed, decompression_error) = if let Some(ref encoding) = content_encoding {
-            if encoding.contains("gzip") {
-                match Self::decompress_gzip(bytes) {
-                    Ok(data) => (Some(data), None),
-                    Err(e) => (None, Some(e)),
-                }
-            } else {
-                (None, None)
-            }
-        } else {
-            (None, None)
+        let (decompressed, decompression_error) = match content_encoding.as_deref().map(|e| Self::decode_content(bytes, e)) {
+            Some(Ok(data)) => (data, None),
+            Some(Err(e)) => (None, Some(e)),
+            None => (None, None),
         };
 
         let working_bytes = decompressed.as_deref().unwrap_or(bytes);
@@ -405,17 +1058,27 @@ impl BodyData {
 
         // Handle truncation
         if working_bytes.len() > max_size {
-            let preview = String::from_utf8_lossy(&working_bytes[..max_size.min(1024)]).to_string();
+            let reason = format!("Body size {} exceeds max {}", working_bytes.len(), max_size);
+
+            if let Some(data) = Self::truncate_json(working_bytes, content_type.as_deref(), max_size) {
+                return Self {
+                    original_encoding: content_encoding,
+                    content_type,
+                    size_bytes: original_size,
+                    stored_size_bytes: data.len(),
+                    truncated: true,
+                    content: BodyContent::TruncatedJson { data, reason },
+                };
+            }
+
+            let preview = Self::text_preview(&working_bytes[..max_size.min(1024)]);
             return Self {
                 original_encoding: content_encoding,
                 content_type,
                 size_bytes: original_size,
                 stored_size_bytes: preview.len(),
                 truncated: true,
-                content: BodyContent::Truncated {
-                    preview,
-                    reason: format!("Body size {} exceeds max {}", working_bytes.len(), max_size),
-                },
+                content: BodyContent::Truncated { preview, reason },
             };
         }
 
@@ -444,18 +1107,169 @@ impl BodyData {
         }
     }
 
-    /// Decompress gzip data
-    fn decompress_gzip(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
-        use flate2::read::GzDecoder;
+    /// Shrink a JSON body to `max_size` bytes, keeping it valid JSON
+    fn truncate_json(bytes: &[u8], content_type: Option<&str>, max_size: usize) -> Option<String> {
+        let looks_like_json = content_type.is_some_and(|ct| ct.contains("json"))
+            || bytes
+                .iter()
+                .find(|b| !b.is_ascii_whitespace())
+                .is_some_and(|b| *b == b'{' || *b == b'[');
+        if !looks_like_json {
+            return None;
+        }
+
+        let value: serde_json::Value = serde_json::from_slice(bytes).ok()?;
+        crate::json_truncate::truncate(&value, max_size)
+    }
+
+    /// Text of a byte prefix, without splitting a UTF-8 character at the cut
+    fn text_preview(bytes: &[u8]) -> String {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => text.to_string(),
+            // Only the last character is incomplete
+            Err(e) if e.error_len().is_none() => {
+                String::from_utf8_lossy(&bytes[..e.valid_up_to()]).to_string()
+            }
+            Err(_) => String::from_utf8_lossy(bytes).to_string(),
+        }
+    }
+
+    /// Undo a `Content-Encoding` list such as `gzip, br`
+    ///
+    /// Codings are listed in the order they were applied, so they are removed
+    /// last to first. Returns None when there is nothing to undo, or when a
+    /// coding is not supported (the body is then stored as it is).
+    fn decode_content(data: &[u8], encoding: &str) -> Result<Option<Vec<u8>>, std::io::Error> {
+        let codings: Vec<String> = encoding
+            .split(',')
+            .map(|c| c.trim().to_ascii_lowercase())
+            .filter(|c| !c.is_empty() && c != "identity")
+            .collect();
+
+        if codings.is_empty() || !codings.iter().all(|c| Self::is_supported_coding(c)) {
+            return Ok(None);
+        }
+
+        let mut decoded = data.to_vec();
+        for coding in codings.iter().rev() {
+            decoded = Self::decompress(&decoded, coding)?;
+        }
+        Ok(Some(decoded))
+    }
+
+    fn is_supported_coding(coding: &str) -> bool {
+        matches!(coding, "gzip" | "x-gzip" | "deflate" | "br" | "zstd")
+    }
+
+    /// Remove one content coding
+    fn decompress(data: &[u8], coding: &str) -> Result<Vec<u8>, std::io::Error> {
+        use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
         use std::io::Read;
 
-        let mut decoder = GzDecoder::new(data);
         let mut decompressed = Vec::new();
-        decoder.read_to_end(&mut decompressed)?;
+        match coding {
+            "gzip" | "x-gzip" => {
+                GzDecoder::new(data).read_to_end(&mut decompressed)?;
+            }
+            "deflate" => {
+                // "deflate" is zlib-wrapped, but some servers send raw deflate
+                if ZlibDecoder::new(data).read_to_end(&mut decompressed).is_err() {
+                    decompressed.clear();
+                    DeflateDecoder::new(data).read_to_end(&mut decompressed)?;
+                }
+            }
+            "br" => {
+                brotli::Decompressor::new(data, 4096).read_to_end(&mut decompressed)?;
+            }
+            "zstd" => {
+                decompressed = zstd::stream::decode_all(data)?;
+            }
+            other => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Unsupported content-encoding: {}", other),
+                ));
+            }
+        }
         Ok(decompressed)
     }
 }
 
+/// Rebuild the full JSON body of a request recorded as a [`BodyContent::Delta`]
+///
+/// `lookup` returns the recorded body of an earlier request by id, with any
+/// blob reference already resolved; chains of deltas are followed back to the
+/// first fully stored body. Returns None if a parent is missing or not JSON.
+/// Full JSON bodies are returned as they are.
+///
+/// Every call walks the whole chain; readers rebuilding many bodies of one log
+/// should keep the rebuilt parents and use [`apply_delta`] instead.
+pub fn reconstruct_body<F>(body: &BodyData, lookup: F) -> Option<serde_json::Value>
+where
+    F: Fn(&Uuid) -> Option<BodyData>,
+{
+    // Walk back to the full body, remembering the deltas on the way
+    let mut deltas = Vec::new();
+    let mut visited = std::collections::HashSet::new();
+    let mut current = body.content.clone();
+    let full = loop {
+        match current {
+            BodyContent::Text { data } => {
+                break serde_json::from_str::<serde_json::Value>(&data).ok()?;
+            }
+            BodyContent::Delta { parent_request_id, .. } => {
+                if !visited.insert(parent_request_id) {
+                    return None;
+                }
+                let parent = lookup(&parent_request_id)?.content;
+                deltas.push(current);
+                current = parent;
+            }
+            _ => return None,
+        }
+    };
+
+    // Replay them oldest first
+    deltas
+        .iter()
+        .rev()
+        .try_fold(full, apply_delta)
+}
+
+/// Apply one [`BodyContent::Delta`] to the full JSON body of its parent
+///
+/// Returns None if `delta` is not a delta or does not fit the parent.
+pub fn apply_delta(mut parent: serde_json::Value, delta: &BodyContent) -> Option<serde_json::Value> {
+    let BodyContent::Delta {
+        prefix_messages,
+        messages,
+        changed,
+        removed,
+        ..
+    } = delta
+    else {
+        return None;
+    };
+    let object = parent.as_object_mut()?;
+
+    let mut all_messages = match object.remove("messages") {
+        Some(serde_json::Value::Array(mut parent_messages)) if parent_messages.len() >= *prefix_messages => {
+            parent_messages.truncate(*prefix_messages);
+            parent_messages
+        }
+        _ => return None,
+    };
+    all_messages.extend(messages.iter().cloned());
+
+    for field in removed {
+        object.remove(field);
+    }
+    object.extend(changed.clone());
+    object.insert("messages".to_string(), serde_json::Value::Array(all_messages));
+
+    Some(parent)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -511,6 +1325,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_body_data_json_truncation_stays_valid() {
+        let messages: Vec<serde_json::Value> = (0..200)
+            .map(|i| serde_json::json!({"role": "user", "content": format!("message {} {}", i, "x".repeat(500))}))
+            .collect();
+        let request = serde_json::json!({"model": "claude-sonnet-4", "messages": messages});
+        let bytes = serde_json::to_vec(&request).unwrap();
+
+        let body = BodyData::from_bytes(&bytes, None, Some("application/json".to_string()), 10_000);
+
+        assert!(body.truncated);
+        assert_eq!(body.size_bytes, bytes.len());
+        match body.content {
+            BodyContent::TruncatedJson { data, reason } => {
+                assert!(data.len() <= 10_000);
+                assert_eq!(body.stored_size_bytes, data.len());
+                assert!(reason.contains("exceeds max"));
+                let parsed: serde_json::Value = serde_json::from_str(&data).unwrap();
+                assert_eq!(parsed["model"], "claude-sonnet-4");
+            }
+            other => panic!("Expected TruncatedJson content, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_body_data_truncation_preview_keeps_utf8_intact() {
+        // 'é' is two bytes, so a 1024-byte cut after one leading byte splits one
+        let mut text = String::from("a");
+        text.push_str(&"é".repeat(1000));
+        let body = BodyData::from_bytes(text.as_bytes(), None, Some("text/plain".to_string()), 1500);
+
+        match body.content {
+            BodyContent::Truncated { preview, .. } => {
+                assert!(!preview.contains('\u{FFFD}'));
+                assert_eq!(preview.len(), 1023);
+            }
+            other => panic!("Expected Truncated content, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_body_data_empty() {
         let body = BodyData::from_bytes(b"", None, None, 1024);
@@ -524,6 +1378,239 @@ mod tests {
         }
     }
 
+    fn encode(data: &[u8], coding: &str) -> Vec<u8> {
+        use flate2::Compression;
+        use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+        use std::io::Write;
+
+        match coding {
+            "gzip" => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            "deflate" => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            "raw-deflate" => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            "br" => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                encoder.write_all(data).unwrap();
+                encoder.into_inner()
+            }
+            "zstd" => zstd::stream::encode_all(data, 0).unwrap(),
+            other => panic!("no test encoder for {}", other),
+        }
+    }
+
+    #[test]
+    fn test_body_data_encodings() {
+        let json = br#"{"model":"claude-sonnet-4","content":[{"type":"text","text":"hello"}]}"#;
+
+        // (Content-Encoding header, codings applied in order)
+        let cases: &[(&str, &[&str])] = &[
+            ("gzip", &["gzip"]),
+            ("x-gzip", &["gzip"]),
+            ("deflate", &["deflate"]),
+            ("deflate", &["raw-deflate"]),
+            ("br", &["br"]),
+            ("zstd", &["zstd"]),
+            ("gzip, br", &["gzip", "br"]),
+            ("deflate, zstd, gzip", &["deflate", "zstd", "gzip"]),
+            ("BR , identity", &["br"]),
+            ("identity", &[]),
+        ];
+
+        for (header, codings) in cases {
+            let encoded = codings.iter().fold(json.to_vec(), |data, c| encode(&data, c));
+            let body = BodyData::from_bytes(
+                &encoded,
+                Some(header.to_string()),
+                Some("application/json".to_string()),
+                1024 * 1024,
+            );
+
+            assert_eq!(body.size_bytes, encoded.len(), "{}", header);
+            assert_eq!(body.original_encoding.as_deref(), Some(*header));
+            match body.content {
+                BodyContent::Text { data } => assert_eq!(data.as_bytes(), json, "{}", header),
+                other => panic!("Expected Text for {}, got {:?}", header, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_body_data_unsupported_encoding_kept_raw() {
+        let encoded = encode(b"hello", "gzip");
+        let body = BodyData::from_bytes(&encoded, Some("gzip, compress".to_string()), None, 1024);
+
+        assert!(matches!(body.content, BodyContent::Binary { .. }));
+        assert_eq!(body.size_bytes, encoded.len());
+    }
+
+    #[test]
+    fn test_body_data_corrupt_encoding() {
+        for coding in ["gzip", "deflate", "br", "zstd"] {
+            let body = BodyData::from_bytes(
+                b"definitely not compressed",
+                Some(coding.to_string()),
+                None,
+                1024,
+            );
+            assert!(
+                matches!(body.content, BodyContent::DecompressionFailed { .. }),
+                "{}: {:?}",
+                coding,
+                body.content
+            );
+        }
+    }
+
+    #[test]
+    fn test_body_data_omitted() {
+        let body = BodyData::omitted(2048, None, Some("application/json".to_string()));
+        let json = serde_json::to_string(&body).unwrap();
+        assert!(json.contains("\"type\":\"Omitted\""));
+
+        let parsed: BodyData = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.size_bytes, 2048);
+        assert_eq!(parsed.stored_size_bytes, 0);
+        assert!(matches!(parsed.content, BodyContent::Omitted));
+    }
+
+    #[test]
+    fn test_proxy_response_ttfb_roundtrip() {
+        let body = BodyData::from_bytes(b"", None, None, 1024);
+        let entry = LogEntry::new_proxy_response(
+            "session".to_string(),
+            "correlation".to_string(),
+            ProxyResponseEvent {
+                ttfb_ms: Some(120),
+                connection_reused: Some(true),
+                timing: Some(ExchangeTiming {
+                    client_tls_ms: Some(8),
+                    proxy_ms: 1,
+                    upstream_dns_ms: Some(3),
+                    upstream_connect_ms: Some(25),
+                    upstream_tls_ms: Some(40),
+                }),
+                ..ProxyResponseEvent::new(Uuid::new_v4(), 200, Headers::default(), body.clone(), 1500)
+            },
+        );
+
+        let json = serde_json::to_string(&entry).unwrap();
+        assert!(json.contains("\"ttfb_ms\":120"));
+
+        let parsed: LogEntry = serde_json::from_str(&json).unwrap();
+        match parsed.event {
+            LogEvent::ProxyResponse(resp) => {
+                assert_eq!(resp.ttfb_ms, Some(120));
+                assert_eq!(resp.duration_ms, 1500);
+                assert_eq!(resp.connection_reused, Some(true));
+                let timing = resp.timing.unwrap();
+                assert_eq!(timing.client_tls_ms, Some(8));
+                assert_eq!(timing.upstream_tls_ms, Some(40));
+            }
+            _ => panic!("Expected ProxyResponse event"),
+        }
+
+        // Older entries without the field still parse
+        let legacy = LogEntry::new_proxy_response(
+            "session".to_string(),
+            "correlation".to_string(),
+            ProxyResponseEvent::new(Uuid::new_v4(), 200, Headers::default(), body, 10),
+        );
+        let json = serde_json::to_string(&legacy).unwrap();
+        assert!(!json.contains("ttfb_ms"));
+        assert!(!json.contains("timing"));
+        assert!(serde_json::from_str::<LogEntry>(&json).is_ok());
+    }
+
+    #[test]
+    fn test_proxy_aborted_response() {
+        let request_id = Uuid::new_v4();
+        let entry = LogEntry::new_proxy_aborted(
+            "session".to_string(),
+            "correlation".to_string(),
+            request_id,
+            30_000,
+            "proxy shutting down".to_string(),
+        );
+
+        let json = serde_json::to_string(&entry).unwrap();
+        let parsed: LogEntry = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.correlation_id, "correlation");
+        match parsed.event {
+            LogEvent::ProxyResponse(resp) => {
+                assert_eq!(resp.request_id, request_id);
+                assert_eq!(resp.status, 0);
+                assert_eq!(resp.aborted.as_deref(), Some("proxy shutting down"));
+            }
+            _ => panic!("Expected ProxyResponse event"),
+        }
+    }
+
+    #[test]
+    fn test_proxy_error_roundtrip() {
+        let request_id = Uuid::new_v4();
+        let entry = LogEntry::new_proxy_error(
+            "session".to_string(),
+            "correlation".to_string(),
+            request_id,
+            UpstreamErrorKind::Timeout,
+            "operation timed out".to_string(),
+            30_000,
+        );
+
+        let json = serde_json::to_string(&entry).unwrap();
+        assert!(json.contains("\"type\":\"ProxyError\""));
+        assert!(json.contains("\"kind\":\"timeout\""));
+
+        let parsed: LogEntry = serde_json::from_str(&json).unwrap();
+        match parsed.event {
+            LogEvent::ProxyError(err) => {
+                assert_eq!(err.request_id, request_id);
+                assert_eq!(err.kind, UpstreamErrorKind::Timeout);
+                assert_eq!(err.status, 504);
+                assert_eq!(err.duration_ms, 30_000);
+            }
+            _ => panic!("Expected ProxyError event"),
+        }
+    }
+
+    #[test]
+    fn test_headers_keep_repeats_order_and_bytes() {
+        let mut map = hyper::HeaderMap::new();
+        map.append("set-cookie", hyper::header::HeaderValue::from_static("a=1"));
+        map.append("via", hyper::header::HeaderValue::from_static("1.1 first"));
+        map.append("set-cookie", hyper::header::HeaderValue::from_static("b=2"));
+        map.append("x-latin1", hyper::header::HeaderValue::from_bytes(b"caf\xe9").unwrap());
+
+        let headers = Headers::from_header_map(&map);
+        assert_eq!(headers.len(), 4);
+        let cookies: Vec<_> = headers.get_all("Set-Cookie").filter_map(HeaderValueData::as_str).collect();
+        assert_eq!(cookies, vec!["a=1", "b=2"]);
+        assert_eq!(headers.get("VIA"), Some("1.1 first"));
+        assert!(headers.get("x-latin1").is_none());
+        assert_eq!(headers.get_all("x-latin1").next().unwrap().to_bytes(), b"caf\xe9");
+
+        let json = serde_json::to_string(&headers).unwrap();
+        assert!(json.starts_with(r#"[["set-cookie","a=1"],["set-cookie","b=2"]"#));
+        assert!(json.contains(r#"["x-latin1",{"base64":"Y2Fm6Q=="}]"#));
+        let parsed: Headers = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, headers);
+
+        let redacted = headers.redacted();
+        assert!(redacted.get_all("set-cookie").all(|v| v.as_str() == Some("[REDACTED]")));
+        assert_eq!(redacted.get("via"), Some("1.1 first"));
+    }
+
     #[test]
     fn test_log_entry_serialization() {
         let entry = LogEntry::new_mcp(
@@ -533,7 +1620,41 @@ mod tests {
         );
 
         let json = serde_json::to_string(&entry).unwrap();
-        assert!(json.contains("\"schema_version\":1"));
+        assert!(json.contains(&format!("\"schema_version\":{}", SCHEMA_VERSION)));
         assert!(json.contains("\"type\":\"Mcp\""));
     }
+
+    #[test]
+    fn test_unknown_event_round_trips() {
+        let line = r#"{"schema_version":2,"timestamp":"2025-01-01T12:00:00Z","date":"2025-01-01","session_id":"s","correlation_id":"c","event":{"type":"ToolTrace","tool":"Bash","spans":[{"ms":12}],"exit":null}}"#;
+
+        let entry = LogEntry::parse_any_version(line).unwrap();
+        let LogEvent::Unknown(unknown) = &entry.event else {
+            panic!("Expected Unknown event, got {:?}", entry.event);
+        };
+        assert_eq!(unknown.kind, "ToolTrace");
+        assert_eq!(unknown.payload["spans"][0]["ms"], 12);
+
+        let written = serde_json::to_value(&entry).unwrap();
+        let original: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert_eq!(written, original);
+    }
+
+    #[test]
+    fn test_malformed_known_event_is_not_unknown() {
+        let line = r#"{"schema_version":2,"timestamp":"2025-01-01T12:00:00Z","date":"2025-01-01","session_id":"s","correlation_id":"c","event":{"type":"ProxyRequest","method":"GET"}}"#;
+        assert!(LogEntry::parse_any_version(line).is_err());
+    }
+
+    #[test]
+    fn test_known_event_types_match_variants() {
+        let schema = json_schema();
+        let tags: Vec<&str> = schema["$defs"]["LogEvent"]["anyOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|variant| variant["properties"]["type"]["const"].as_str())
+            .collect();
+        assert_eq!(tags, KNOWN_EVENT_TYPES);
+    }
 }
@@ -0,0 +1,276 @@
+//! Linking proxy traffic to Claude Code sessions
+//!
+//! Hook events carry the real Claude Code session id, but an API request only
+//! identifies its session indirectly. The correlator tries, in order:
+//!
+//! - the `x-claude-code-session-id` request header
+//! - `metadata.user_id` in the request body, which names the session
+//! - the hook events in today's log: when exactly one session fired a hook in
+//!   the last few minutes, the request almost certainly belongs to it. Only
+//!   `/v1/messages` requests are matched this way, since it reads the log.
+//!
+//! Requests matching none of these keep a random session id.
+
+use crate::log_writer::LogWriter;
+use crate::schema::{LogEntry, LogEvent, SessionSource};
+use chrono::{DateTime, Utc};
+use hyper::HeaderMap;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const SESSION_HEADER: &str = "x-claude-code-session-id";
+
+/// Present in every serialized hook event
+const HOOK_MARKER: &[u8] = br#""type":"Hook""#;
+
+/// How long after its last hook event a session still counts as active
+const HOOK_WINDOW_SECS: i64 = 600;
+
+/// Bytes at the end of the log scanned for recent hook events
+const HOOK_SCAN_BYTES: u64 = 8 * 1024 * 1024;
+
+/// The only path correlated by hook timing
+const MESSAGES_PATH: &str = "/v1/messages";
+
+/// How long a scan of the log is reused before reading it again
+const HOOK_SCAN_TTL: Duration = Duration::from_secs(2);
+
+/// Resolves the Claude Code session an API request belongs to
+#[derive(Clone)]
+pub struct SessionCorrelator {
+    log_writer: Arc<LogWriter>,
+    last_scan: Arc<Mutex<Option<HookScan>>>,
+}
+
+/// Result of the latest scan for an active hook session
+struct HookScan {
+    at: Instant,
+    session: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RequestEnvelope {
+    metadata: Option<RequestMetadata>,
+}
+
+#[derive(Deserialize)]
+struct RequestMetadata {
+    user_id: Option<String>,
+}
+
+impl SessionCorrelator {
+    pub fn new(log_writer: Arc<LogWriter>) -> Self {
+        Self {
+            log_writer,
+            last_scan: Arc::default(),
+        }
+    }
+
+    /// Session id of the request to `path` and how it was found
+    pub async fn resolve(&self, path: &str, headers: &HeaderMap, body: &[u8]) -> Option<(String, SessionSource)> {
+        if let Some(id) = session_from_header(headers) {
+            return Some((id, SessionSource::Header));
+        }
+        if let Some(id) = session_from_metadata(body) {
+            return Some((id, SessionSource::Metadata));
+        }
+        if path != MESSAGES_PATH {
+            return None;
+        }
+        self.session_from_hooks()
+            .await
+            .map(|id| (id, SessionSource::HookTiming))
+    }
+
+    async fn session_from_hooks(&self) -> Option<String> {
+        if let Some(scan) = self.last_scan.lock().unwrap().as_ref() {
+            if scan.at.elapsed() < HOOK_SCAN_TTL {
+                return scan.session.clone();
+            }
+        }
+
+        let now = Utc::now();
+        let path = self.log_writer.get_log_file_path(&now.format("%Y-%m-%d").to_string());
+        let session = tokio::task::spawn_blocking(move || {
+            recent_hook_entries(&path)
+                .ok()
+                .and_then(|entries| active_hook_session(&entries, now))
+        })
+        .await
+        .ok()
+        .flatten();
+
+        *self.last_scan.lock().unwrap() = Some(HookScan {
+            at: Instant::now(),
+            session: session.clone(),
+        });
+        session
+    }
+}
+
+fn session_from_header(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(SESSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(String::from)
+}
+
+/// Session named by `metadata.user_id`
+///
+/// Claude Code sends either `user_<hash>_account_<uuid>_session_<uuid>` or a
+/// JSON object with a `session_id` field, serialized into the string.
+fn session_from_metadata(body: &[u8]) -> Option<String> {
+    let envelope: RequestEnvelope = serde_json::from_slice(body).ok()?;
+    let user_id = envelope.metadata?.user_id?;
+
+    let session = match serde_json::from_str::<serde_json::Value>(&user_id) {
+        Ok(value) => value.get("session_id")?.as_str()?.to_string(),
+        Err(_) => user_id.rsplit_once("_session_")?.1.to_string(),
+    };
+    (!session.is_empty()).then_some(session)
+}
+
+/// Hook events near the end of a log file
+///
+/// Only lines that are hook events are parsed; request and response lines
+/// around them can be large.
+fn recent_hook_entries(path: &Path) -> io::Result<Vec<LogEntry>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let start = len.saturating_sub(HOOK_SCAN_BYTES);
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+
+    // The first line is partial unless the scan starts at the beginning
+    let skip = if start > 0 { 1 } else { 0 };
+    Ok(data
+        .split(|b| *b == b'\n')
+        .skip(skip)
+        .filter(|line| line.windows(HOOK_MARKER.len()).any(|w| w == HOOK_MARKER))
+        .filter_map(|line| std::str::from_utf8(line).ok())
+        .filter_map(|line| LogEntry::parse_any_version(line).ok())
+        .collect())
+}
+
+/// The only session with hook activity in the last [`HOOK_WINDOW_SECS`]
+///
+/// Returns None when no session, or more than one, is active: with several
+/// sessions running side by side timing alone cannot tell them apart.
+fn active_hook_session(entries: &[LogEntry], now: DateTime<Utc>) -> Option<String> {
+    let mut latest: HashMap<&str, (DateTime<Utc>, &str)> = HashMap::new();
+    for entry in entries {
+        if let LogEvent::Hook(hook) = &entry.event {
+            latest.insert(&entry.session_id, (entry.timestamp, &hook.event_type));
+        }
+    }
+
+    let mut active = latest.into_iter().filter(|(_, (at, event_type))| {
+        *event_type != "SessionEnd" && (now - *at).num_seconds() <= HOOK_WINDOW_SECS
+    });
+    match (active.next(), active.next()) {
+        (Some((session, _)), None) => Some(session.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(session: &str, event_type: &str, secs_ago: i64) -> LogEntry {
+        let mut entry = LogEntry::new_hook(
+            session.to_string(),
+            event_type.to_string(),
+            None,
+            None,
+            None,
+            None,
+            HashMap::new(),
+        );
+        entry.timestamp = Utc::now() - chrono::Duration::seconds(secs_ago);
+        entry
+    }
+
+    #[test]
+    fn test_session_from_header() {
+        let mut headers = HeaderMap::new();
+        assert!(session_from_header(&headers).is_none());
+
+        headers.insert(SESSION_HEADER, "abc-123".parse().unwrap());
+        assert_eq!(session_from_header(&headers).as_deref(), Some("abc-123"));
+    }
+
+    #[test]
+    fn test_session_from_metadata() {
+        let body = br#"{"model":"claude-sonnet-4-5","metadata":{"user_id":"user_ab12_account_1111_session_2222-3333"}}"#;
+        assert_eq!(session_from_metadata(body).as_deref(), Some("2222-3333"));
+
+        let body = br#"{"metadata":{"user_id":"{\"device_id\":\"d\",\"session_id\":\"4444\"}"}}"#;
+        assert_eq!(session_from_metadata(body).as_deref(), Some("4444"));
+
+        assert!(session_from_metadata(br#"{"metadata":{"user_id":"user_ab12"}}"#).is_none());
+        assert!(session_from_metadata(br#"{"messages":[]}"#).is_none());
+        assert!(session_from_metadata(b"not json").is_none());
+    }
+
+    #[test]
+    fn test_single_recent_hook_session_wins() {
+        let now = Utc::now();
+        let entries = vec![
+            hook("old", "PreToolUse", 3600),
+            hook("ended", "UserPromptSubmit", 30),
+            hook("ended", "SessionEnd", 10),
+            hook("current", "UserPromptSubmit", 20),
+            LogEntry::new_mcp("mcp".to_string(), "INFO".to_string(), "ignored".to_string()),
+        ];
+        assert_eq!(active_hook_session(&entries, now).as_deref(), Some("current"));
+    }
+
+    #[test]
+    fn test_recent_hook_entries_skips_other_lines() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let writer = LogWriter::new(temp_dir.path().to_path_buf()).unwrap();
+        let entry = hook("current", "PreToolUse", 0);
+        writer.write_sync(&entry).unwrap();
+        writer
+            .write_sync(&LogEntry::new_mcp("mcp".to_string(), "INFO".to_string(), "x".to_string()))
+            .unwrap();
+
+        let entries = recent_hook_entries(&writer.get_log_file_path(&entry.date)).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].session_id, "current");
+    }
+
+    #[tokio::test]
+    async fn test_hook_timing_only_for_messages() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let writer = Arc::new(LogWriter::new(temp_dir.path().to_path_buf()).unwrap());
+        writer.write_sync(&hook("current", "UserPromptSubmit", 0)).unwrap();
+        let correlator = SessionCorrelator::new(writer);
+
+        let headers = HeaderMap::new();
+        assert!(correlator.resolve("/v1/models", &headers, b"").await.is_none());
+        assert!(correlator.last_scan.lock().unwrap().is_none());
+
+        let (session, source) = correlator.resolve(MESSAGES_PATH, &headers, b"{}").await.unwrap();
+        assert_eq!(session, "current");
+        assert_eq!(source, SessionSource::HookTiming);
+    }
+
+    #[test]
+    fn test_concurrent_hook_sessions_are_ambiguous() {
+        let now = Utc::now();
+        let entries = vec![hook("a", "PreToolUse", 5), hook("b", "PreToolUse", 5)];
+        assert!(active_hook_session(&entries, now).is_none());
+        assert!(active_hook_session(&[], now).is_none());
+    }
+}
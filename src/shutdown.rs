@@ -0,0 +1,249 @@
+//! Graceful shutdown support for the proxy
+//!
+//! Tracks exchanges whose request event has been written but whose response
+//! event has not, so shutdown can wait for them and close out any that do not
+//! finish in time. Served client connections are tracked too, so keep-alive
+//! and MITM connections stop taking new requests once shutdown starts.
+
+use crate::schema::Origin;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Notify};
+use uuid::Uuid;
+
+/// Resolve when the process is asked to stop (SIGTERM or Ctrl-C)
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received Ctrl-C"),
+        _ = terminate => tracing::info!("Received SIGTERM"),
+    }
+}
+
+/// An exchange whose response has not been recorded yet
+#[derive(Debug, Clone)]
+pub struct PendingExchange {
+    pub session_id: String,
+    pub correlation_id: String,
+    pub origin: Origin,
+    pub started: Instant,
+}
+
+/// Registry of in-flight exchanges and served connections
+#[derive(Clone, Default)]
+pub struct InFlight {
+    inner: Arc<InFlightState>,
+}
+
+struct InFlightState {
+    exchanges: Mutex<HashMap<Uuid, PendingExchange>>,
+    changed: Notify,
+    /// Set once shutdown starts; every served connection holds a receiver
+    closing: watch::Sender<bool>,
+}
+
+impl Default for InFlightState {
+    fn default() -> Self {
+        Self {
+            exchanges: Mutex::default(),
+            changed: Notify::new(),
+            closing: watch::Sender::new(false),
+        }
+    }
+}
+
+impl InFlight {
+    /// Track an exchange until the returned guard is dropped
+    pub fn register(&self, request_id: Uuid, session_id: &str, correlation_id: &str, origin: &Origin) -> InFlightGuard {
+        self.inner.exchanges.lock().unwrap().insert(
+            request_id,
+            PendingExchange {
+                session_id: session_id.to_string(),
+                correlation_id: correlation_id.to_string(),
+                origin: origin.clone(),
+                started: Instant::now(),
+            },
+        );
+        InFlightGuard {
+            in_flight: self.clone(),
+            request_id,
+        }
+    }
+
+    /// Number of exchanges still in flight
+    pub fn len(&self) -> usize {
+        self.inner.exchanges.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Wait up to `timeout` for every exchange to finish
+    ///
+    /// Returns the exchanges still pending at the deadline, removing them
+    /// from the registry.
+    pub async fn drain(&self, timeout: Duration) -> Vec<(Uuid, PendingExchange)> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            // Register for wakeups before checking, so a finish in between isn't missed
+            let changed = self.inner.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            if self.is_empty() {
+                return Vec::new();
+            }
+            if tokio::time::timeout_at(deadline, changed).await.is_err() {
+                return self.inner.exchanges.lock().unwrap().drain().collect();
+            }
+        }
+    }
+
+    /// Serve a client connection until it ends
+    ///
+    /// Once [`InFlight::close_connections`] is called, `graceful_shutdown` is
+    /// invoked on it: requests already received are answered, then it closes.
+    pub async fn serve<C, F>(&self, conn: C, graceful_shutdown: F) -> C::Output
+    where
+        C: Future,
+        F: FnOnce(Pin<&mut C>),
+    {
+        let mut closing = self.inner.closing.subscribe();
+        tokio::pin!(conn);
+        tokio::select! {
+            output = conn.as_mut() => return output,
+            _ = closing.wait_for(|closing| *closing) => {}
+        }
+        graceful_shutdown(conn.as_mut());
+        conn.await
+    }
+
+    /// Ask every served connection to close, returning how many were open
+    pub fn close_connections(&self) -> usize {
+        self.inner.closing.send_replace(true);
+        self.inner.closing.receiver_count()
+    }
+
+    fn finish(&self, request_id: &Uuid) {
+        self.inner.exchanges.lock().unwrap().remove(request_id);
+        self.inner.changed.notify_waiters();
+    }
+}
+
+/// Keeps an exchange registered as in flight while alive
+pub struct InFlightGuard {
+    in_flight: InFlight,
+    request_id: Uuid,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight.finish(&self.request_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origin() -> Origin {
+        Origin::run(Uuid::new_v4())
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_exchanges() {
+        let in_flight = InFlight::default();
+        let guard = in_flight.register(Uuid::new_v4(), "session", "correlation", &origin());
+        assert_eq!(in_flight.len(), 1);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(guard);
+        });
+
+        let aborted = in_flight.drain(Duration::from_secs(5)).await;
+        assert!(aborted.is_empty());
+        assert_eq!(in_flight.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_drain_returns_unfinished_exchanges() {
+        let in_flight = InFlight::default();
+        let request_id = Uuid::new_v4();
+        let _stuck = in_flight.register(request_id, "session", "correlation", &origin());
+        let finished = in_flight.register(Uuid::new_v4(), "session", "other", &origin());
+        drop(finished);
+
+        let aborted = in_flight.drain(Duration::from_millis(20)).await;
+        assert_eq!(aborted.len(), 1);
+        assert_eq!(aborted[0].0, request_id);
+        assert_eq!(aborted[0].1.correlation_id, "correlation");
+        assert_eq!(in_flight.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_close_connections_ends_keep_alive() {
+        use hyper::server::conn::http1;
+        use hyper::service::service_fn;
+        use hyper_util::rt::TokioIo;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let in_flight = InFlight::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = in_flight.clone();
+        let served = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(|_req| async {
+                Ok::<_, std::convert::Infallible>(hyper::Response::new("ok".to_string()))
+            });
+            let conn = http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+            server.serve(conn, |conn| conn.graceful_shutdown()).await
+        });
+
+        // One answered request leaves a keep-alive connection open
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nhost: test\r\n\r\n").await.unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !response.ends_with(b"ok") {
+            let n = client.read(&mut buf).await.unwrap();
+            response.extend_from_slice(&buf[..n]);
+        }
+
+        assert_eq!(in_flight.close_connections(), 1);
+        tokio::time::timeout(Duration::from_secs(5), served)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+    }
+}
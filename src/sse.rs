@@ -0,0 +1,467 @@
+//! Server-sent event parsing and Claude message reassembly
+//!
+//! Streaming `/v1/messages` responses arrive as `text/event-stream` bodies.
+//! This module splits them into individual events and replays the
+//! `message_start` / `content_block_*` / `message_delta` / `message_stop`
+//! sequence to rebuild the final message the client ended up with.
+
+use crate::schema::{BodyContent, BodyData, ContentBlock, SseEvent, StreamedMessage};
+use serde_json::{Map, Value};
+
+/// Split an event-stream body into events
+///
+/// Events are separated by blank lines. Multiple `data:` lines are joined with
+/// newlines, comment lines (starting with `:`) and unknown fields are ignored.
+pub fn parse_events(text: &str) -> Vec<SseEvent> {
+    let mut events = Vec::new();
+    let mut event_name: Option<String> = None;
+    let mut data_lines: Vec<&str> = Vec::new();
+
+    let mut flush = |event_name: &mut Option<String>, data_lines: &mut Vec<&str>| {
+        if event_name.is_none() && data_lines.is_empty() {
+            return;
+        }
+        let raw = data_lines.join("\n");
+        let data = serde_json::from_str(&raw).unwrap_or(Value::String(raw));
+        events.push(SseEvent {
+            event: event_name.take(),
+            data,
+        });
+        data_lines.clear();
+    };
+
+    for line in text.lines() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if line.is_empty() {
+            flush(&mut event_name, &mut data_lines);
+            continue;
+        }
+        if line.starts_with(':') {
+            continue;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+
+        match field {
+            "event" => event_name = Some(value.to_string()),
+            "data" => data_lines.push(value),
+            _ => {}
+        }
+    }
+
+    // A stream cut off mid-event still yields what was received
+    flush(&mut event_name, &mut data_lines);
+
+    events
+}
+
+/// Rebuild the final message from a sequence of Claude streaming events
+///
+/// Returns `None` when the stream does not look like a Claude message stream
+/// (no `message_start` event).
+pub fn assemble_message(events: &[SseEvent]) -> Option<StreamedMessage> {
+    let mut message: Option<StreamedMessage> = None;
+    let mut usage = Map::new();
+    // Blocks are kept as JSON while deltas are applied, with any partial
+    // tool input accumulated alongside
+    let mut blocks: Vec<(Value, String)> = Vec::new();
+
+    for event in events {
+        let event_type = event
+            .data
+            .get("type")
+            .and_then(Value::as_str)
+            .or(event.event.as_deref())
+            .unwrap_or_default();
+
+        match event_type {
+            "message_start" => {
+                let start = event.data.get("message").cloned().unwrap_or_default();
+                merge_usage(&mut usage, start.get("usage"));
+                message = Some(StreamedMessage {
+                    id: string_field(&start, "id"),
+                    model: string_field(&start, "model"),
+                    role: string_field(&start, "role"),
+                    ..Default::default()
+                });
+            }
+            "content_block_start" => {
+                let index = block_index(&event.data, blocks.len());
+                let block = event
+                    .data
+                    .get("content_block")
+                    .cloned()
+                    .unwrap_or_default();
+                // Blocks start in order; an index past the next one is bogus
+                // and must not size the list
+                match index.cmp(&blocks.len()) {
+                    std::cmp::Ordering::Less => blocks[index] = (block, String::new()),
+                    std::cmp::Ordering::Equal => blocks.push((block, String::new())),
+                    std::cmp::Ordering::Greater => continue,
+                }
+            }
+            "content_block_delta" => {
+                let index = block_index(&event.data, blocks.len().saturating_sub(1));
+                let Some((block, partial_json)) = blocks.get_mut(index) else {
+                    continue;
+                };
+                let Some(delta) = event.data.get("delta") else {
+                    continue;
+                };
+                match delta.get("type").and_then(Value::as_str) {
+                    Some("text_delta") => append_field(block, "text", delta),
+                    Some("thinking_delta") => append_field(block, "thinking", delta),
+                    Some("signature_delta") => {
+                        if let (Some(obj), Some(sig)) = (block.as_object_mut(), delta.get("signature")) {
+                            obj.insert("signature".to_string(), sig.clone());
+                        }
+                    }
+                    Some("input_json_delta") => {
+                        if let Some(part) = delta.get("partial_json").and_then(Value::as_str) {
+                            partial_json.push_str(part);
+                        }
+                    }
+                    Some("citations_delta") => {
+                        if let (Some(obj), Some(citation)) = (block.as_object_mut(), delta.get("citation")) {
+                            let citations = obj
+                                .entry("citations")
+                                .or_insert_with(|| Value::Array(Vec::new()));
+                            if let Some(list) = citations.as_array_mut() {
+                                list.push(citation.clone());
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            "message_delta" => {
+                if let Some(msg) = message.as_mut() {
+                    if let Some(delta) = event.data.get("delta") {
+                        if let Some(reason) = string_field(delta, "stop_reason") {
+                            msg.stop_reason = Some(reason);
+                        }
+                        if let Some(sequence) = string_field(delta, "stop_sequence") {
+                            msg.stop_sequence = Some(sequence);
+                        }
+                    }
+                }
+                merge_usage(&mut usage, event.data.get("usage"));
+            }
+            "message_stop" => {
+                if let Some(msg) = message.as_mut() {
+                    msg.complete = true;
+                }
+            }
+            "error" => {
+                if let Some(msg) = message.as_mut() {
+                    msg.error = Some(event.data.get("error").cloned().unwrap_or_else(|| event.data.clone()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut message = message?;
+
+    message.content = blocks
+        .into_iter()
+        .filter(|(block, _)| !block.is_null())
+        .map(|(mut block, partial_json)| {
+            // Tool input streams as JSON fragments; an empty stream means `{}`
+            if !partial_json.is_empty() {
+                let input = serde_json::from_str(&partial_json).unwrap_or(Value::String(partial_json));
+                if let Some(obj) = block.as_object_mut() {
+                    obj.insert("input".to_string(), input);
+                }
+            }
+            serde_json::from_value(block.clone()).unwrap_or(ContentBlock::Other(block))
+        })
+        .collect();
+
+    if !usage.is_empty() {
+        message.usage = serde_json::from_value(Value::Object(usage)).ok();
+    }
+
+    Some(message)
+}
+
+/// Replace a text event-stream body with its reassembled message
+///
+/// Bodies that are not Claude message streams, or were truncated or failed
+/// to decode, are returned unchanged.
+pub fn assemble_body(body: BodyData, keep_events: bool) -> BodyData {
+    let BodyContent::Text { data } = &body.content else {
+        return body;
+    };
+
+    let events = parse_events(data);
+    let Some(message) = assemble_message(&events) else {
+        return body;
+    };
+
+    BodyData {
+        content: BodyContent::EventStream {
+            message,
+            events: keep_events.then_some(events),
+        },
+        ..body
+    }
+}
+
+/// Record a `text/event-stream` response body
+///
+/// Claude message streams are assembled from the whole body, even one over
+/// `max_size`, so a long stream keeps its final message and usage. Past the
+/// limit only the raw events asked for with `keep_events` are dropped. Other
+/// streams are recorded like any body, truncated at `max_size`.
+pub fn stream_body(
+    bytes: &[u8],
+    content_encoding: Option<String>,
+    content_type: Option<String>,
+    max_size: usize,
+    keep_events: bool,
+) -> BodyData {
+    let full = BodyData::from_bytes(bytes, content_encoding.clone(), content_type.clone(), usize::MAX);
+    if full.stored_size_bytes <= max_size {
+        return assemble_body(full, keep_events);
+    }
+
+    match assemble_body(full, false) {
+        BodyData {
+            content: BodyContent::EventStream { message, .. },
+            ..
+        } => {
+            let content = BodyContent::EventStream {
+                message,
+                events: None,
+            };
+            BodyData {
+                original_encoding: content_encoding,
+                content_type,
+                size_bytes: bytes.len(),
+                stored_size_bytes: serde_json::to_string(&content).map_or(0, |s| s.len()),
+                truncated: keep_events,
+                content,
+            }
+        }
+        _ => BodyData::from_bytes(bytes, content_encoding, content_type, max_size),
+    }
+}
+
+fn merge_usage(usage: &mut Map<String, Value>, update: Option<&Value>) {
+    if let Some(Value::Object(update)) = update {
+        for (key, value) in update {
+            // Later events report cumulative counts; nulls carry no information
+            if !value.is_null() {
+                usage.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn block_index(data: &Value, fallback: usize) -> usize {
+    data.get("index")
+        .and_then(Value::as_u64)
+        .map(|i| i as usize)
+        .unwrap_or(fallback)
+}
+
+fn append_field(block: &mut Value, field: &str, delta: &Value) {
+    let Some(piece) = delta.get(field).and_then(Value::as_str) else {
+        return;
+    };
+    if let Some(obj) = block.as_object_mut() {
+        let entry = obj
+            .entry(field.to_string())
+            .or_insert_with(|| Value::String(String::new()));
+        if let Value::String(existing) = entry {
+            existing.push_str(piece);
+        }
+    }
+}
+
+fn string_field(value: &Value, field: &str) -> Option<String> {
+    value.get(field).and_then(Value::as_str).map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STREAM: &str = concat!(
+        "event: message_start\n",
+        "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_01\",\"type\":\"message\",\"role\":\"assistant\",\"model\":\"claude-sonnet-4-5\",\"content\":[],\"stop_reason\":null,\"usage\":{\"input_tokens\":25,\"cache_creation_input_tokens\":null,\"cache_read_input_tokens\":100,\"output_tokens\":1}}}\n",
+        "\n",
+        "event: content_block_start\n",
+        "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n",
+        "\n",
+        "event: ping\n",
+        "data: {\"type\": \"ping\"}\n",
+        "\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Let me \"}}\n",
+        "\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"check.\"}}\n",
+        "\n",
+        "event: content_block_stop\n",
+        "data: {\"type\":\"content_block_stop\",\"index\":0}\n",
+        "\n",
+        "event: content_block_start\n",
+        "data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_01\",\"name\":\"Bash\",\"input\":{}}}\n",
+        "\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"command\\\": \"}}\n",
+        "\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"\\\"ls -la\\\"}\"}}\n",
+        "\n",
+        "event: content_block_stop\n",
+        "data: {\"type\":\"content_block_stop\",\"index\":1}\n",
+        "\n",
+        "event: message_delta\n",
+        "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":42}}\n",
+        "\n",
+        "event: message_stop\n",
+        "data: {\"type\":\"message_stop\"}\n",
+        "\n",
+    );
+
+    #[test]
+    fn test_parse_events() {
+        let events = parse_events(STREAM);
+        assert_eq!(events.len(), 12);
+        assert_eq!(events[0].event.as_deref(), Some("message_start"));
+        assert_eq!(events[2].data["type"], "ping");
+    }
+
+    #[test]
+    fn test_parse_events_multiline_and_crlf() {
+        let events = parse_events(": keep-alive\r\nevent: note\r\ndata: line one\r\ndata: line two\r\n\r\n");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event.as_deref(), Some("note"));
+        assert_eq!(events[0].data, Value::String("line one\nline two".to_string()));
+    }
+
+    #[test]
+    fn test_assemble_message() {
+        let message = assemble_message(&parse_events(STREAM)).unwrap();
+
+        assert_eq!(message.id.as_deref(), Some("msg_01"));
+        assert_eq!(message.model.as_deref(), Some("claude-sonnet-4-5"));
+        assert_eq!(message.stop_reason.as_deref(), Some("tool_use"));
+        assert!(message.complete);
+        assert_eq!(message.content.len(), 2);
+
+        match &message.content[0] {
+            ContentBlock::Text { text } => assert_eq!(text, "Let me check."),
+            other => panic!("Expected text block, got {:?}", other),
+        }
+        match &message.content[1] {
+            ContentBlock::ToolUse { id, name, input } => {
+                assert_eq!(id, "toolu_01");
+                assert_eq!(name, "Bash");
+                assert_eq!(input["command"], "ls -la");
+            }
+            other => panic!("Expected tool_use block, got {:?}", other),
+        }
+
+        let usage = message.usage.unwrap();
+        assert_eq!(usage.input_tokens, 25);
+        assert_eq!(usage.output_tokens, 42);
+        assert_eq!(usage.cache_creation_input_tokens, 0);
+        assert_eq!(usage.cache_read_input_tokens, 100);
+    }
+
+    #[test]
+    fn test_assemble_incomplete_stream() {
+        let cut = &STREAM[..STREAM.find("event: message_delta").unwrap()];
+        let message = assemble_message(&parse_events(cut)).unwrap();
+        assert!(!message.complete);
+        assert!(message.stop_reason.is_none());
+        assert_eq!(message.content.len(), 2);
+    }
+
+    #[test]
+    fn test_assemble_ignores_out_of_range_block_index() {
+        let bogus = concat!(
+            "event: content_block_start\n",
+            "data: {\"type\":\"content_block_start\",\"index\":4000000000,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n",
+            "\n",
+            "event: content_block_start\n",
+            "data: {\"type\":\"content_block_start\",\"index\":18446744073709551615,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n",
+            "\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":4000000000,\"delta\":{\"type\":\"text_delta\",\"text\":\"x\"}}\n",
+            "\n",
+        );
+        let stream = STREAM.replacen("event: content_block_start", &format!("{}event: content_block_start", bogus), 1);
+
+        let message = assemble_message(&parse_events(&stream)).unwrap();
+        assert!(message.complete);
+        assert_eq!(message.content.len(), 2);
+    }
+
+    #[test]
+    fn test_assemble_body_keeps_events_when_asked() {
+        let body = BodyData::from_bytes(
+            STREAM.as_bytes(),
+            None,
+            Some("text/event-stream".to_string()),
+            1024 * 1024,
+        );
+
+        match assemble_body(body.clone(), false).content {
+            BodyContent::EventStream { events, .. } => assert!(events.is_none()),
+            _ => panic!("Expected EventStream content"),
+        }
+        match assemble_body(body, true).content {
+            BodyContent::EventStream { events, .. } => assert_eq!(events.unwrap().len(), 12),
+            _ => panic!("Expected EventStream content"),
+        }
+    }
+
+    #[test]
+    fn test_stream_body_over_limit_keeps_message() {
+        let content_type = Some("text/event-stream".to_string());
+        let body = stream_body(STREAM.as_bytes(), None, content_type.clone(), 512, true);
+        assert!(body.truncated);
+        assert_eq!(body.size_bytes, STREAM.len());
+        match body.content {
+            BodyContent::EventStream { message, events } => {
+                assert!(message.complete);
+                assert_eq!(message.content.len(), 2);
+                let usage = message.usage.unwrap();
+                assert_eq!(usage.input_tokens, 25);
+                assert_eq!(usage.output_tokens, 42);
+                assert!(events.is_none());
+            }
+            other => panic!("Expected EventStream content, got {:?}", other),
+        }
+
+        // Within the limit the raw events are kept as asked
+        let body = stream_body(STREAM.as_bytes(), None, content_type.clone(), 1024 * 1024, true);
+        assert!(!body.truncated);
+        assert!(matches!(body.content, BodyContent::EventStream { events: Some(_), .. }));
+
+        // Other streams are still truncated
+        let other = "event: ping\ndata: {\"i\":0}\n\n".repeat(100);
+        let body = stream_body(other.as_bytes(), None, content_type, 512, false);
+        assert!(matches!(body.content, BodyContent::Truncated { .. }));
+    }
+
+    #[test]
+    fn test_assemble_body_ignores_other_streams() {
+        let body = BodyData::from_bytes(
+            b"event: ping\ndata: {\"i\":0}\n\n",
+            None,
+            Some("text/event-stream".to_string()),
+            1024,
+        );
+        assert!(matches!(assemble_body(body, false).content, BodyContent::Text { .. }));
+    }
+}
@@ -1,9 +1,16 @@
 //! Efficient tail reading for log files
+//!
+//! Entries written with older schema versions are upgraded, bodies stored in
+//! the blob store are resolved, and delta-encoded request bodies are rebuilt,
+//! so callers always see full, current entries. Reports that only need the
+//! recorded metadata can skip the bodies with `read_all_entries_as_recorded`.
 
-use crate::schema::LogEntry;
+use crate::blob_store::BlobStore;
+use crate::schema::{apply_delta, BodyContent, LogEntry, LogEvent};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, Read, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 
 /// Read the last N lines from a file efficiently without loading the entire file
 ///
@@ -40,10 +47,8 @@ pub fn read_last_n_lines(file_path: &PathBuf, n: usize) -> Result<Vec<LogEntry>,
             if buffer[i] == b'\n' {
                 if start < i {
                     // We have a complete line
-                    if let Ok(line_str) = std::str::from_utf8(&buffer[start..i]) {
-                        if let Ok(entry) = serde_json::from_str::<LogEntry>(line_str) {
-                            entries.push(entry);
-                        }
+                    if let Some(entry) = parse_line(&buffer[start..i]) {
+                        entries.push(entry);
                     }
                 }
                 start = i + 1;
@@ -71,9 +76,178 @@ pub fn read_last_n_lines(file_path: &PathBuf, n: usize) -> Result<Vec<LogEntry>,
         entries.drain(0..entries.len() - n);
     }
 
+    resolve_blobs(file_path, &mut entries);
+    if has_deltas(&entries) {
+        // Parents can be anywhere earlier in the day, not just in the tail
+        let mut requests = read_requests(file_path)?;
+        resolve_blobs(file_path, &mut requests);
+        rebuild_deltas(&mut requests);
+
+        let mut rebuilt: HashMap<uuid::Uuid, BodyContent> = requests
+            .into_iter()
+            .filter_map(|entry| match entry.event {
+                LogEvent::ProxyRequest(req) => Some((req.id, req.body.content)),
+                _ => None,
+            })
+            .collect();
+        for entry in entries.iter_mut().filter(|entry| is_delta(entry)) {
+            let LogEvent::ProxyRequest(req) = &mut entry.event else {
+                continue;
+            };
+            if let Some(content) = rebuilt.remove(&req.id) {
+                req.body.content = content;
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Read every entry of a log file in order
+///
+/// Used by reports that aggregate over a whole day. Lines that cannot be read
+/// even after upgrading are skipped, matching `read_last_n_lines`.
+pub fn read_all_entries(file_path: &PathBuf) -> Result<Vec<LogEntry>, io::Error> {
+    let mut entries = read_all_entries_as_recorded(file_path)?;
+    resolve_blobs(file_path, &mut entries);
+    if has_deltas(&entries) {
+        rebuild_deltas(&mut entries);
+    }
+    Ok(entries)
+}
+
+/// Read every entry of a log file in order, leaving bodies as recorded
+///
+/// Blob references and delta-encoded request bodies are not followed, which
+/// saves reading them back for reports that only use the other fields.
+pub fn read_all_entries_as_recorded(file_path: &PathBuf) -> Result<Vec<LogEntry>, io::Error> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut entries = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(entry) = parse_line(line.as_bytes()) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Marker present in every serialized request event
+const REQUEST_MARKER: &[u8] = br#""type":"ProxyRequest""#;
+
+fn parse_line(line: &[u8]) -> Option<LogEntry> {
+    let line = std::str::from_utf8(line).ok()?;
+    match LogEntry::parse_any_version(line) {
+        Ok(entry) => Some(entry),
+        Err(e) => {
+            tracing::warn!("Skipping unreadable log line: {}", e);
+            None
+        }
+    }
+}
+
+fn resolve_blobs(file_path: &Path, entries: &mut [LogEntry]) {
+    let store = BlobStore::for_log_file(file_path);
+    for entry in entries {
+        store.resolve_entry(entry);
+    }
+}
+
+fn is_delta(entry: &LogEntry) -> bool {
+    matches!(
+        &entry.event,
+        LogEvent::ProxyRequest(req) if matches!(req.body.content, BodyContent::Delta { .. })
+    )
+}
+
+fn has_deltas(entries: &[LogEntry]) -> bool {
+    entries.iter().any(is_delta)
+}
+
+/// Request entries of a whole log file; other lines are not parsed
+fn read_requests(file_path: &Path) -> io::Result<Vec<LogEntry>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut entries = Vec::new();
+    for line in reader.split(b'\n') {
+        let line = line?;
+        if !line.windows(REQUEST_MARKER.len()).any(|w| w == REQUEST_MARKER) {
+            continue;
+        }
+        if let Some(entry) = parse_line(&line) {
+            entries.push(entry);
+        }
+    }
     Ok(entries)
 }
 
+/// Replace delta-encoded request bodies with the full bodies they stand for
+///
+/// `entries` are in file order with blob references resolved. Each body is
+/// rebuilt once, from its parent's rebuilt body, which is kept only until its
+/// last child has used it. Bodies whose parent is missing or comes later in
+/// the file are left as deltas.
+fn rebuild_deltas(entries: &mut [LogEntry]) {
+    // Children still waiting for each parent
+    let mut waiting: HashMap<uuid::Uuid, usize> = HashMap::new();
+    for entry in entries.iter() {
+        if let LogEvent::ProxyRequest(req) = &entry.event {
+            if let BodyContent::Delta { parent_request_id, .. } = &req.body.content {
+                *waiting.entry(*parent_request_id).or_default() += 1;
+            }
+        }
+    }
+
+    let mut rebuilt: HashMap<uuid::Uuid, serde_json::Value> = HashMap::new();
+    for entry in entries.iter_mut() {
+        let LogEvent::ProxyRequest(req) = &mut entry.event else {
+            continue;
+        };
+        let full = match &req.body.content {
+            BodyContent::Delta { parent_request_id, .. } => {
+                let Some(full) = take_parent(&mut rebuilt, &mut waiting, parent_request_id)
+                    .and_then(|parent| apply_delta(parent, &req.body.content))
+                else {
+                    continue;
+                };
+                req.body.content = BodyContent::Text {
+                    data: full.to_string(),
+                };
+                full
+            }
+            BodyContent::Text { data } if waiting.contains_key(&req.id) => {
+                match serde_json::from_str(data) {
+                    Ok(full) => full,
+                    Err(_) => continue,
+                }
+            }
+            _ => continue,
+        };
+        if waiting.contains_key(&req.id) {
+            rebuilt.insert(req.id, full);
+        }
+    }
+}
+
+/// The rebuilt body of `parent` for one of its children, handed over rather
+/// than copied to the last one
+fn take_parent(
+    rebuilt: &mut HashMap<uuid::Uuid, serde_json::Value>,
+    waiting: &mut HashMap<uuid::Uuid, usize>,
+    parent: &uuid::Uuid,
+) -> Option<serde_json::Value> {
+    let children = waiting.get_mut(parent)?;
+    *children -= 1;
+    if *children == 0 {
+        waiting.remove(parent);
+        rebuilt.remove(parent)
+    } else {
+        rebuilt.get(parent).cloned()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -134,6 +308,231 @@ mod tests {
         assert_eq!(entries.len(), 3); // Should return only what's available
     }
 
+    #[test]
+    fn test_read_all_entries_in_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let writer = LogWriter::new(temp_dir.path().to_path_buf()).unwrap();
+
+        for i in 0..5 {
+            let entry = LogEntry::new_mcp(
+                format!("session-{}", i),
+                "INFO".to_string(),
+                format!("Message {}", i),
+            );
+            writer.write_sync(&entry).unwrap();
+        }
+
+        let log_path = writer.get_log_file_path(
+            &chrono::Utc::now().format("%Y-%m-%d").to_string()
+        );
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&log_path)
+            .and_then(|mut f| std::io::Write::write_all(&mut f, b"not json\n"))
+            .unwrap();
+
+        let entries = read_all_entries(&log_path).unwrap();
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0].session_id, "session-0");
+        assert_eq!(entries[4].session_id, "session-4");
+    }
+
+    #[test]
+    fn test_old_schema_versions_upgraded() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("2025-01-01.jsonl");
+        let v1 = r#"{"schema_version":1,"timestamp":"2025-01-01T12:00:00Z","date":"2025-01-01","session_id":"old","correlation_id":"c","event":{"type":"ProxyResponse","status":200,"headers":{"content-type":"text/plain"},"body":{"size_bytes":0,"stored_size_bytes":0,"truncated":false,"content":{"type":"Empty"}},"duration_ms":5,"request_id":"7f2c1b9e-4d3a-4c5b-9e8f-0a1b2c3d4e5f"}}"#;
+        std::fs::write(&log_path, format!("{}\n", v1)).unwrap();
+
+        for entries in [read_last_n_lines(&log_path, 1).unwrap(), read_all_entries(&log_path).unwrap()] {
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].session_id, "old");
+            assert_eq!(entries[0].schema_version, crate::schema::SCHEMA_VERSION);
+        }
+    }
+
+    #[test]
+    fn test_blob_references_resolved() {
+        use crate::schema::{BodyContent, BodyData, LogEvent};
+
+        let temp_dir = TempDir::new().unwrap();
+        let writer = LogWriter::new(temp_dir.path().to_path_buf()).unwrap();
+        let store = BlobStore::new(temp_dir.path());
+
+        let text = "conversation ".repeat(100);
+        let body = BodyData::from_bytes(text.as_bytes(), None, None, usize::MAX);
+        let entry = LogEntry::new_proxy_response(
+            "session".to_string(),
+            "correlation".to_string(),
+            crate::schema::ProxyResponseEvent::new(
+                uuid::Uuid::new_v4(),
+                200,
+                crate::schema::Headers::default(),
+                store.offload(body, 64),
+                10,
+            ),
+        );
+        writer.write_sync(&entry).unwrap();
+
+        let log_path = writer.get_log_file_path(&entry.date);
+        let raw = std::fs::read_to_string(&log_path).unwrap();
+        assert!(raw.contains("BlobRef"));
+        assert!(!raw.contains("conversation"));
+
+        for entries in [read_last_n_lines(&log_path, 1).unwrap(), read_all_entries(&log_path).unwrap()] {
+            let LogEvent::ProxyResponse(resp) = &entries[0].event else {
+                panic!("Expected ProxyResponse event");
+            };
+            match &resp.body.content {
+                BodyContent::Text { data } => assert_eq!(data, &text),
+                other => panic!("Expected resolved Text content, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_delta_bodies_rebuilt_from_offloaded_parent() {
+        use crate::schema::{BodyData, Headers, ProxyRequestEvent};
+        use serde_json::json;
+
+        fn request(id: uuid::Uuid, body: BodyData) -> LogEntry {
+            LogEntry::new_proxy_request(
+                "session".to_string(),
+                "correlation".to_string(),
+                ProxyRequestEvent::new(
+                    id,
+                    "POST".to_string(),
+                    "https://api.anthropic.com/v1/messages".to_string(),
+                    Headers::default(),
+                    body,
+                ),
+            )
+        }
+
+        let temp_dir = TempDir::new().unwrap();
+        let writer = LogWriter::new(temp_dir.path().to_path_buf()).unwrap();
+        let store = BlobStore::new(temp_dir.path());
+
+        let first_turn = json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "conversation ".repeat(100)}]
+        });
+        let parent_id = uuid::Uuid::new_v4();
+        let parent = BodyData::from_bytes(first_turn.to_string().as_bytes(), None, None, usize::MAX);
+        writer.write_sync(&request(parent_id, store.offload(parent, 64))).unwrap();
+        writer
+            .write_sync(&LogEntry::new_mcp("s".to_string(), "INFO".to_string(), "between".to_string()))
+            .unwrap();
+
+        let reply = json!({"role": "assistant", "content": "hello"});
+        let mut delta = BodyData::from_bytes(b"", None, None, usize::MAX);
+        delta.content = BodyContent::Delta {
+            parent_request_id: parent_id,
+            prefix_messages: 1,
+            messages: vec![reply.clone()],
+            changed: serde_json::Map::new(),
+            removed: Vec::new(),
+        };
+        let entry = request(uuid::Uuid::new_v4(), delta);
+        writer.write_sync(&entry).unwrap();
+
+        let log_path = writer.get_log_file_path(&entry.date);
+        let mut expected = first_turn.clone();
+        expected["messages"].as_array_mut().unwrap().push(reply);
+
+        for entries in [read_last_n_lines(&log_path, 1).unwrap(), read_all_entries(&log_path).unwrap()] {
+            let LogEvent::ProxyRequest(req) = &entries.last().unwrap().event else {
+                panic!("Expected ProxyRequest event");
+            };
+            match &req.body.content {
+                BodyContent::Text { data } => {
+                    assert_eq!(serde_json::from_str::<serde_json::Value>(data).unwrap(), expected)
+                }
+                other => panic!("Expected rebuilt Text content, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_delta_chain_with_retried_turn_rebuilt() {
+        use crate::schema::{BodyData, Headers, ProxyRequestEvent};
+        use serde_json::json;
+
+        fn request(id: uuid::Uuid, content: BodyContent) -> LogEntry {
+            let mut body = BodyData::from_bytes(b"", None, None, usize::MAX);
+            body.content = content;
+            LogEntry::new_proxy_request(
+                "session".to_string(),
+                "correlation".to_string(),
+                ProxyRequestEvent::new(
+                    id,
+                    "POST".to_string(),
+                    "https://api.anthropic.com/v1/messages".to_string(),
+                    Headers::default(),
+                    body,
+                ),
+            )
+        }
+
+        fn delta(parent: uuid::Uuid, prefix_messages: usize, content: &str) -> BodyContent {
+            BodyContent::Delta {
+                parent_request_id: parent,
+                prefix_messages,
+                messages: vec![json!({"role": "user", "content": content})],
+                changed: serde_json::Map::new(),
+                removed: Vec::new(),
+            }
+        }
+
+        let temp_dir = TempDir::new().unwrap();
+        let writer = LogWriter::new(temp_dir.path().to_path_buf()).unwrap();
+
+        // A full first turn, a second turn sent twice, then a third turn
+        let ids: Vec<uuid::Uuid> = (0..4).map(|_| uuid::Uuid::new_v4()).collect();
+        let first = json!({"model": "claude-sonnet-4-5", "messages": [{"role": "user", "content": "one"}]});
+        let bodies = [
+            BodyContent::Text { data: first.to_string() },
+            delta(ids[0], 1, "two"),
+            delta(ids[0], 1, "two again"),
+            delta(ids[2], 2, "three"),
+        ];
+        for (id, content) in ids.iter().zip(bodies) {
+            writer.write_sync(&request(*id, content)).unwrap();
+        }
+        let log_path = writer.get_log_file_path(&chrono::Utc::now().format("%Y-%m-%d").to_string());
+
+        let contents = |entries: &[LogEntry]| -> Vec<Vec<String>> {
+            entries
+                .iter()
+                .map(|entry| match &entry.event {
+                    LogEvent::ProxyRequest(req) => match &req.body.content {
+                        BodyContent::Text { data } => serde_json::from_str::<serde_json::Value>(data).unwrap()
+                            ["messages"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .map(|m| m["content"].as_str().unwrap().to_string())
+                            .collect(),
+                        other => panic!("Expected rebuilt Text content, got {:?}", other),
+                    },
+                    _ => panic!("Expected ProxyRequest event"),
+                })
+                .collect()
+        };
+        let expected = vec![
+            vec!["one"],
+            vec!["one", "two"],
+            vec!["one", "two again"],
+            vec!["one", "two again", "three"],
+        ];
+        assert_eq!(contents(&read_all_entries(&log_path).unwrap()), expected);
+        assert_eq!(contents(&read_last_n_lines(&log_path, 2).unwrap()), expected[2..]);
+
+        let recorded = read_all_entries_as_recorded(&log_path).unwrap();
+        assert_eq!(recorded.len(), 4);
+        assert!(recorded[1..].iter().all(is_delta));
+    }
+
     #[test]
     fn test_read_empty_file() {
         let temp_dir = TempDir::new().unwrap();
@@ -0,0 +1,863 @@
+//! Shared upstream HTTPS client for the proxy
+//!
+//! A single pooled client is built when the proxy starts and reused for every
+//! forwarded request, so keep-alive connections to the API survive between
+//! calls. The connectors tag each new connection with a marker that travels
+//! with every response served over it, which lets the proxy tell whether a
+//! response came over a fresh or a reused connection and how long opening
+//! the connection took (DNS, TCP connect, TLS).
+//!
+//! When an upstream (chained) proxy is configured, connections to hosts not
+//! covered by `no_proxy` are opened through it with HTTP CONNECT, both for
+//! the forwarding client and for passthrough tunnels.
+
+use crate::proxy_config::{UpstreamConfig, UpstreamProxyConfig};
+use crate::schema::UpstreamErrorKind;
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::Uri;
+use hyper::rt::{Read, ReadBufCursor, Write};
+use hyper_rustls::{HttpsConnector, MaybeHttpsStream};
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::{Connected, Connection};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::task::{self, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Pooled client used to forward requests upstream
+pub type UpstreamClient = Client<TlsTimingConnector, Full<Bytes>>;
+
+/// Build the shared upstream client from configuration
+pub fn build_client(
+    config: &UpstreamConfig,
+    proxy: Option<&UpstreamProxyConfig>,
+) -> Result<UpstreamClient> {
+    let proxy = proxy.map(UpstreamProxy::from_config).transpose()?.map(Arc::new);
+
+    let https = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()?
+        .https_or_http()
+        .enable_http1()
+        .enable_http2()
+        .wrap_connector(TcpConnector { proxy });
+
+    let client = Client::builder(TokioExecutor::new())
+        .pool_max_idle_per_host(config.pool_max_idle_per_host)
+        .pool_idle_timeout(Duration::from_secs(config.pool_idle_timeout_secs))
+        .build(TlsTimingConnector {
+            inner: https,
+            timeout: non_zero_secs(config.connect_timeout_secs),
+        });
+
+    Ok(client)
+}
+
+fn non_zero_secs(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Read and total timeouts of one forwarded request
+///
+/// Every wait on the upstream (response headers, each body chunk) must end
+/// before [`Deadline::next`]: within the read timeout of the wait starting,
+/// and before the total timeout of the exchange runs out.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    read: Option<Duration>,
+    total: Option<tokio::time::Instant>,
+}
+
+impl Deadline {
+    /// Start the clock for a request sent now
+    pub fn start(config: &UpstreamConfig) -> Self {
+        Self {
+            read: non_zero_secs(config.read_timeout_secs),
+            total: non_zero_secs(config.total_timeout_secs)
+                .map(|total| tokio::time::Instant::now() + total),
+        }
+    }
+
+    /// When a wait starting now has to be over, if ever
+    pub fn next(&self) -> Option<tokio::time::Instant> {
+        let read = self.read.map(|read| tokio::time::Instant::now() + read);
+        match (read, self.total) {
+            (Some(read), Some(total)) => Some(read.min(total)),
+            (read, total) => read.or(total),
+        }
+    }
+
+    /// Run `future`, failing with a timeout error if it misses the deadline
+    pub async fn run<F: Future>(&self, future: F) -> io::Result<F::Output> {
+        match self.next() {
+            Some(at) => tokio::time::timeout_at(at, future)
+                .await
+                .map_err(|_| timed_out()),
+            None => Ok(future.await),
+        }
+    }
+}
+
+/// Error reported when an upstream wait misses its deadline
+pub fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "upstream did not respond in time")
+}
+
+/// Time spent opening an upstream connection, in milliseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConnectTiming {
+    pub dns_ms: u64,
+    pub connect_ms: u64,
+    /// None for plain HTTP connections
+    pub tls_ms: Option<u64>,
+}
+
+/// How the upstream connection behind a response was obtained
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpstreamConnection {
+    /// Whether the connection had already served an earlier request
+    pub reused: bool,
+    /// Handshake timing, only reported to the first request on a connection
+    pub timing: Option<ConnectTiming>,
+}
+
+/// Marker attached to every upstream connection
+///
+/// The client copies it into the extensions of each response served over the
+/// connection, so all responses on one connection share the same state.
+#[derive(Clone, Default)]
+pub struct ConnectionMarker(Arc<MarkerState>);
+
+#[derive(Default)]
+struct MarkerState {
+    used: AtomicBool,
+    dns: Duration,
+    connect: Duration,
+    tls: OnceLock<Duration>,
+}
+
+impl ConnectionMarker {
+    fn new(dns: Duration, connect: Duration) -> Self {
+        Self(Arc::new(MarkerState {
+            dns,
+            connect,
+            ..Default::default()
+        }))
+    }
+
+    /// Record the TLS handshake given the total time to open the connection
+    fn record_tls(&self, total: Duration) {
+        let tcp = self.0.dns + self.0.connect;
+        let _ = self.0.tls.set(total.saturating_sub(tcp));
+    }
+
+    /// Mark the connection as used, returning whether it had been used before
+    pub fn mark_used(&self) -> bool {
+        self.0.used.swap(true, Ordering::SeqCst)
+    }
+
+    fn timing(&self) -> ConnectTiming {
+        ConnectTiming {
+            dns_ms: self.0.dns.as_millis() as u64,
+            connect_ms: self.0.connect.as_millis() as u64,
+            tls_ms: self.0.tls.get().map(|d| d.as_millis() as u64),
+        }
+    }
+}
+
+/// Describe the upstream connection that served this response
+///
+/// Marks the connection as used, so call it once per response.
+pub fn connection_info<B>(response: &hyper::Response<B>) -> Option<UpstreamConnection> {
+    let marker = response.extensions().get::<ConnectionMarker>()?;
+    let reused = marker.mark_used();
+    Some(UpstreamConnection {
+        reused,
+        timing: (!reused).then(|| marker.timing()),
+    })
+}
+
+/// Classify a failed upstream request by walking its chain of causes
+pub fn classify_error(error: &(dyn StdError + 'static)) -> UpstreamErrorKind {
+    let mut current = Some(error);
+    while let Some(err) = current {
+        if err.is::<DnsError>() {
+            return UpstreamErrorKind::Dns;
+        }
+        if err.is::<rustls::Error>() {
+            return UpstreamErrorKind::Tls;
+        }
+        if let Some(io_err) = err.downcast_ref::<io::Error>() {
+            // io::Error::source() skips the wrapped error, so look at it directly
+            if let Some(inner) = io_err.get_ref() {
+                let kind = classify_error(inner);
+                if kind != UpstreamErrorKind::Other {
+                    return kind;
+                }
+            }
+            match io_err.kind() {
+                io::ErrorKind::ConnectionRefused => return UpstreamErrorKind::ConnectRefused,
+                io::ErrorKind::TimedOut => return UpstreamErrorKind::Timeout,
+                io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof => return UpstreamErrorKind::Reset,
+                _ => {}
+            }
+        }
+        if let Some(hyper_err) = err.downcast_ref::<hyper::Error>() {
+            if hyper_err.is_incomplete_message() || hyper_err.is_canceled() {
+                return UpstreamErrorKind::Reset;
+            }
+            if hyper_err.is_timeout() {
+                return UpstreamErrorKind::Timeout;
+            }
+        }
+        current = err.source();
+    }
+
+    // Connect errors that matched nothing more specific
+    match error.downcast_ref::<hyper_util::client::legacy::Error>() {
+        Some(client_err) if client_err.is_connect() => UpstreamErrorKind::Connect,
+        _ => UpstreamErrorKind::Other,
+    }
+}
+
+/// Format an error with all of its causes
+pub fn error_chain(error: &(dyn StdError + 'static)) -> String {
+    let mut message = error.to_string();
+    let mut current = error.source();
+    while let Some(err) = current {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        current = err.source();
+    }
+    message
+}
+
+/// Host name resolution failure, kept distinct from connect errors
+#[derive(Debug)]
+struct DnsError {
+    host: String,
+    source: Option<io::Error>,
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to resolve {}", self.host)
+    }
+}
+
+impl StdError for DnsError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_ref().map(|e| e as _)
+    }
+}
+
+impl DnsError {
+    fn into_io(host: &str, source: Option<io::Error>) -> io::Error {
+        io::Error::other(DnsError {
+            host: host.to_string(),
+            source,
+        })
+    }
+}
+
+/// Upstream proxy that connections are chained through
+#[derive(Clone)]
+pub struct UpstreamProxy {
+    host: String,
+    port: u16,
+    /// Pre-encoded `Proxy-Authorization` value
+    authorization: Option<String>,
+    no_proxy: Vec<String>,
+}
+
+impl UpstreamProxy {
+    /// Parse the proxy settings, adding hosts from the `NO_PROXY` environment variable
+    pub fn from_config(config: &UpstreamProxyConfig) -> Result<Self> {
+        let uri: Uri = config
+            .url
+            .parse()
+            .with_context(|| format!("Invalid upstream proxy URL: {}", config.url))?;
+        if !matches!(uri.scheme_str(), None | Some("http")) {
+            bail!("Upstream proxy must be an http:// URL: {}", config.url);
+        }
+        let host = uri
+            .host()
+            .with_context(|| format!("Upstream proxy URL has no host: {}", config.url))?
+            .to_string();
+
+        let authorization = config.username.as_ref().map(|user| {
+            let credentials = format!("{}:{}", user, config.password.as_deref().unwrap_or(""));
+            format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(credentials)
+            )
+        });
+
+        let env_no_proxy = std::env::var("NO_PROXY")
+            .or_else(|_| std::env::var("no_proxy"))
+            .unwrap_or_default();
+        let no_proxy = config
+            .no_proxy
+            .iter()
+            .map(String::as_str)
+            .chain(env_no_proxy.split(','))
+            .map(|entry| entry.trim().to_ascii_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .collect();
+
+        Ok(Self {
+            host,
+            port: uri.port_u16().unwrap_or(80),
+            authorization,
+            no_proxy,
+        })
+    }
+
+    /// Whether connections to `host` go through this proxy
+    ///
+    /// `NO_PROXY` entries match the host itself and its subdomains; a leading
+    /// `.` or `*.` is ignored and `*` bypasses the proxy for every host.
+    pub fn applies_to(&self, host: &str) -> bool {
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_ascii_lowercase();
+        !self.no_proxy.iter().any(|entry| {
+            let domain = entry.trim_start_matches('*').trim_start_matches('.');
+            entry == "*"
+                || host == domain
+                || host
+                    .strip_suffix(domain)
+                    .is_some_and(|sub| sub.ends_with('.'))
+        })
+    }
+
+    /// Open a connection to the proxy itself, for plain http requests sent
+    /// to it in absolute-form
+    async fn connect_forwarding(&self) -> io::Result<(TcpStream, Duration, Duration)> {
+        connect_direct(&self.host, self.port).await
+    }
+
+    /// Open a tunnel to `host:port` through the proxy with HTTP CONNECT
+    async fn connect(&self, host: &str, port: u16) -> io::Result<(TcpStream, Duration, Duration)> {
+        let (mut stream, dns, connect) = connect_direct(&self.host, self.port).await?;
+        let start = Instant::now();
+
+        let target = if host.contains(':') && !host.starts_with('[') {
+            format!("[{}]:{}", host, port)
+        } else {
+            format!("{}:{}", host, port)
+        };
+        let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+        if let Some(auth) = &self.authorization {
+            request.push_str(&format!("Proxy-Authorization: {}\r\n", auth));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // Read the reply byte by byte so nothing past the header is consumed
+        let mut reply = Vec::new();
+        while !reply.ends_with(b"\r\n\r\n") {
+            if reply.len() > 8192 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "upstream proxy CONNECT reply too long",
+                ));
+            }
+            let mut byte = [0u8; 1];
+            if stream.read(&mut byte).await? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "upstream proxy closed the connection during CONNECT",
+                ));
+            }
+            reply.push(byte[0]);
+        }
+
+        let reply = String::from_utf8_lossy(&reply);
+        let status_line = reply.lines().next().unwrap_or_default();
+        if status_line.split_whitespace().nth(1) != Some("200") {
+            return Err(io::Error::other(format!(
+                "upstream proxy refused CONNECT to {}: {}",
+                target, status_line
+            )));
+        }
+
+        Ok((stream, dns, connect + start.elapsed()))
+    }
+}
+
+/// `Proxy-Authorization` for a plain http request forwarded to the upstream proxy
+///
+/// https requests are tunneled and authenticate on the CONNECT instead.
+pub fn forward_authorization(config: Option<&UpstreamProxyConfig>, uri: &Uri) -> Option<String> {
+    if uri.scheme_str() == Some("https") {
+        return None;
+    }
+    let proxy = UpstreamProxy::from_config(config?).ok()?;
+    if !proxy.applies_to(uri.host()?) {
+        return None;
+    }
+    proxy.authorization
+}
+
+/// Open a TCP connection to `host:port`, through the upstream proxy if it applies
+///
+/// Returns the stream with the time spent on DNS and on connecting (including
+/// the CONNECT exchange when going through a proxy).
+pub async fn connect_tcp(
+    proxy: Option<&UpstreamProxy>,
+    host: &str,
+    port: u16,
+) -> io::Result<(TcpStream, Duration, Duration)> {
+    match proxy {
+        Some(proxy) if proxy.applies_to(host) => proxy.connect(host, port).await,
+        _ => connect_direct(host, port).await,
+    }
+}
+
+/// [`connect_tcp`] bounded by the configured connect timeout (0 disables)
+pub async fn connect_tcp_with_timeout(
+    proxy: Option<&UpstreamProxy>,
+    host: &str,
+    port: u16,
+    config: &UpstreamConfig,
+) -> io::Result<(TcpStream, Duration, Duration)> {
+    let connecting = connect_tcp(proxy, host, port);
+    match non_zero_secs(config.connect_timeout_secs) {
+        Some(timeout) => tokio::time::timeout(timeout, connecting)
+            .await
+            .map_err(|_| connect_timed_out(timeout))?,
+        None => connecting.await,
+    }
+}
+
+fn connect_timed_out(timeout: Duration) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("connect timed out after {}s", timeout.as_secs()),
+    )
+}
+
+async fn connect_direct(host: &str, port: u16) -> io::Result<(TcpStream, Duration, Duration)> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let start = Instant::now();
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| DnsError::into_io(host, Some(e)))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(DnsError::into_io(host, None));
+    }
+    let dns = start.elapsed();
+
+    let stream = TcpStream::connect(addrs.as_slice()).await?;
+    let connect = start.elapsed().saturating_sub(dns);
+    stream.set_nodelay(true)?;
+
+    Ok((stream, dns, connect))
+}
+
+/// TCP connector that times DNS resolution and connect separately
+///
+/// Each connection it opens is tagged with a [`ConnectionMarker`].
+#[derive(Clone)]
+pub struct TcpConnector {
+    proxy: Option<Arc<UpstreamProxy>>,
+}
+
+impl tower_service::Service<Uri> for TcpConnector {
+    type Response = TrackedStream<TokioIo<TcpStream>>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let proxy = self.proxy.clone();
+        Box::pin(async move {
+            let host = dst
+                .host()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URI has no host"))?;
+            let port = dst
+                .port_u16()
+                .unwrap_or(if dst.scheme_str() == Some("https") { 443 } else { 80 });
+
+            // Plain http goes to the proxy in absolute-form; only TLS is tunneled
+            let forwarded = proxy
+                .as_deref()
+                .filter(|proxy| dst.scheme_str() != Some("https") && proxy.applies_to(host));
+            let (stream, dns, connect) = match forwarded {
+                Some(proxy) => proxy.connect_forwarding().await?,
+                None => connect_tcp(proxy.as_deref(), host, port).await?,
+            };
+
+            Ok(TrackedStream {
+                inner: TokioIo::new(stream),
+                marker: ConnectionMarker::new(dns, connect),
+                proxied: forwarded.is_some(),
+            })
+        })
+    }
+}
+
+/// HTTPS connector wrapper that records the TLS handshake time on the marker
+/// and bounds the whole connection setup by the connect timeout
+#[derive(Clone)]
+pub struct TlsTimingConnector {
+    inner: HttpsConnector<TcpConnector>,
+    timeout: Option<Duration>,
+}
+
+impl tower_service::Service<Uri> for TlsTimingConnector {
+    type Response = MaybeHttpsStream<TrackedStream<TokioIo<TcpStream>>>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let start = Instant::now();
+        let connecting = self.inner.call(dst);
+        let timeout = self.timeout;
+        Box::pin(async move {
+            let stream = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, connecting)
+                    .await
+                    .map_err(|_| connect_timed_out(timeout))??,
+                None => connecting.await?,
+            };
+            if let MaybeHttpsStream::Https(tls) = &stream {
+                tls.inner().get_ref().0.inner().marker.record_tls(start.elapsed());
+            }
+            Ok(stream)
+        })
+    }
+}
+
+/// Upstream stream carrying its connection marker
+pub struct TrackedStream<S> {
+    inner: S,
+    marker: ConnectionMarker,
+    /// Connected to the upstream proxy, which gets requests in absolute-form
+    proxied: bool,
+}
+
+impl<S: Connection> Connection for TrackedStream<S> {
+    fn connected(&self) -> Connected {
+        self.inner
+            .connected()
+            .proxy(self.proxied)
+            .extra(self.marker.clone())
+    }
+}
+
+impl<S: Read + Unpin> Read for TrackedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: Write + Unpin> Write for TrackedStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_marker_reports_reuse() {
+        let marker = ConnectionMarker::default();
+        let shared = marker.clone();
+
+        assert!(!marker.mark_used());
+        assert!(shared.mark_used());
+        assert!(marker.mark_used());
+    }
+
+    #[test]
+    fn test_connection_info_from_extensions() {
+        let mut response = hyper::Response::new(());
+        assert_eq!(connection_info(&response), None);
+
+        let marker = ConnectionMarker::new(Duration::from_millis(5), Duration::from_millis(20));
+        marker.record_tls(Duration::from_millis(60));
+        response.extensions_mut().insert(marker);
+
+        let first = connection_info(&response).unwrap();
+        assert!(!first.reused);
+        assert_eq!(
+            first.timing,
+            Some(ConnectTiming {
+                dns_ms: 5,
+                connect_ms: 20,
+                tls_ms: Some(35),
+            })
+        );
+
+        // Later requests on the same connection did not pay for the handshake
+        let second = connection_info(&response).unwrap();
+        assert!(second.reused);
+        assert_eq!(second.timing, None);
+    }
+
+    fn proxy_config(url: &str, no_proxy: &[&str]) -> UpstreamProxyConfig {
+        UpstreamProxyConfig {
+            url: url.to_string(),
+            username: Some("alice".to_string()),
+            password: Some("secret".to_string()),
+            no_proxy: no_proxy.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_upstream_proxy_no_proxy() {
+        let proxy = UpstreamProxy::from_config(&proxy_config(
+            "http://proxy.corp.example:3128",
+            &["localhost", ".corp.example", "10.0.0.1"],
+        ))
+        .unwrap();
+
+        assert!(proxy.applies_to("api.anthropic.com"));
+        assert!(!proxy.applies_to("localhost"));
+        assert!(!proxy.applies_to("git.corp.example"));
+        assert!(!proxy.applies_to("corp.example"));
+        assert!(proxy.applies_to("notcorp.example"));
+        assert!(!proxy.applies_to("10.0.0.1"));
+
+        let bypass_all = UpstreamProxy::from_config(&proxy_config("http://proxy:3128", &["*"])).unwrap();
+        assert!(!bypass_all.applies_to("api.anthropic.com"));
+    }
+
+    #[test]
+    fn test_upstream_proxy_rejects_https_url() {
+        assert!(UpstreamProxy::from_config(&proxy_config("https://proxy:3128", &[])).is_err());
+    }
+
+    /// Minimal proxy that answers one request with `reply` and returns the request it saw
+    async fn fake_proxy(reply: &'static str) -> (UpstreamProxyConfig, tokio::task::JoinHandle<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(reply.as_bytes()).await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        (proxy_config(&format!("http://{}", addr), &[]), handle)
+    }
+
+    #[tokio::test]
+    async fn test_upstream_proxy_connect_with_auth() {
+        let (config, handle) = fake_proxy("HTTP/1.1 200 Connection established\r\n\r\n").await;
+        let proxy = UpstreamProxy::from_config(&config).unwrap();
+
+        connect_tcp(Some(&proxy), "api.anthropic.com", 443).await.unwrap();
+
+        let request = handle.await.unwrap();
+        assert!(request.starts_with("CONNECT api.anthropic.com:443 HTTP/1.1\r\n"));
+        // base64("alice:secret")
+        assert!(request.contains("Proxy-Authorization: Basic YWxpY2U6c2VjcmV0\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_proxy_connect_refused() {
+        let (config, _handle) =
+            fake_proxy("HTTP/1.1 407 Proxy Authentication Required\r\n\r\n").await;
+        let proxy = UpstreamProxy::from_config(&config).unwrap();
+
+        let err = connect_tcp(Some(&proxy), "api.anthropic.com", 443).await.unwrap_err();
+        assert!(err.to_string().contains("407"));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_proxy_forwards_plain_http() {
+        let (config, handle) = fake_proxy("HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok").await;
+        let client = build_client(&UpstreamConfig::default(), Some(&config)).unwrap();
+
+        let uri: Uri = "http://example.com/status?x=1".parse().unwrap();
+        let auth = forward_authorization(Some(&config), &uri).unwrap();
+        let req = hyper::Request::get(uri)
+            .header(hyper::header::PROXY_AUTHORIZATION, auth)
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let response = client.request(req).await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        // Sent to the proxy as-is rather than tunneled
+        let request = handle.await.unwrap();
+        assert!(request.starts_with("GET http://example.com/status?x=1 HTTP/1.1\r\n"), "{}", request);
+        assert!(request.to_ascii_lowercase().contains("proxy-authorization: basic ywxpy2u6c2vjcmv0\r\n"));
+
+        // https requests authenticate on the CONNECT tunnel instead
+        let https: Uri = "https://example.com/".parse().unwrap();
+        assert!(forward_authorization(Some(&config), &https).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout_covers_silent_proxy() {
+        // Accepts the CONNECT but never answers it
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+        let proxy = UpstreamProxy::from_config(&proxy_config(&format!("http://{}", addr), &[])).unwrap();
+
+        let config = UpstreamConfig {
+            connect_timeout_secs: 1,
+            ..Default::default()
+        };
+        let err = connect_tcp_with_timeout(Some(&proxy), "api.anthropic.com", 443, &config)
+            .await
+            .unwrap_err();
+        assert_eq!(classify_error(&err), UpstreamErrorKind::Timeout);
+        assert!(err.to_string().contains("connect timed out after 1s"));
+    }
+
+    async fn failed_request(uri: &str) -> hyper_util::client::legacy::Error {
+        let client = build_client(&UpstreamConfig::default(), None).unwrap();
+        let req = hyper::Request::get(uri).body(Full::new(Bytes::new())).unwrap();
+        client.request(req).await.unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn test_classify_connect_refused() {
+        // Bind and drop a listener to get a port nothing listens on
+        let port = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let err = failed_request(&format!("http://127.0.0.1:{}/", port)).await;
+        assert_eq!(classify_error(&err), UpstreamErrorKind::ConnectRefused);
+    }
+
+    #[tokio::test]
+    async fn test_classify_dns_failure() {
+        let err = failed_request("http://does-not-exist.invalid/").await;
+        assert_eq!(classify_error(&err), UpstreamErrorKind::Dns);
+        assert!(error_chain(&err).contains("failed to resolve does-not-exist.invalid"));
+    }
+
+    #[tokio::test]
+    async fn test_classify_tls_failure() {
+        // A plain-text server answering a TLS client hello
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).await;
+            let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").await;
+        });
+
+        let err = failed_request(&format!("https://127.0.0.1:{}/", port)).await;
+        assert_eq!(classify_error(&err), UpstreamErrorKind::Tls);
+    }
+
+    #[tokio::test]
+    async fn test_classify_reset() {
+        // Accept the request, then close without answering
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).await;
+        });
+
+        let err = failed_request(&format!("http://127.0.0.1:{}/", port)).await;
+        assert_eq!(classify_error(&err), UpstreamErrorKind::Reset);
+    }
+
+    #[tokio::test]
+    async fn test_deadline_read_timeout() {
+        let config = UpstreamConfig {
+            read_timeout_secs: 1,
+            ..Default::default()
+        };
+        let deadline = Deadline::start(&config);
+        assert!(deadline.next().is_some());
+
+        let err = deadline.run(std::future::pending::<()>()).await.unwrap_err();
+        assert_eq!(classify_error(&err), UpstreamErrorKind::Timeout);
+
+        let unlimited = Deadline::start(&UpstreamConfig {
+            read_timeout_secs: 0,
+            ..Default::default()
+        });
+        assert!(unlimited.next().is_none());
+        assert_eq!(unlimited.run(async { 42 }).await.unwrap(), 42);
+    }
+
+    #[test]
+    fn test_classify_timeout_status() {
+        let err = io::Error::from(io::ErrorKind::TimedOut);
+        let kind = classify_error(&err);
+        assert_eq!(kind, UpstreamErrorKind::Timeout);
+        assert_eq!(kind.status(), 504);
+        assert_eq!(UpstreamErrorKind::Reset.status(), 502);
+    }
+}
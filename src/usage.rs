@@ -0,0 +1,223 @@
+//! Token usage extraction and cost summaries
+//!
+//! Claude API responses report token usage either in the JSON body or, for
+//! streamed responses, across the `message_start` and `message_delta` events.
+//! This module pulls that usage out of recorded bodies and aggregates the
+//! usage stored on response events into daily, per-session and per-model totals.
+
+use crate::schema::{BodyContent, BodyData, LogEntry, LogEvent, MessageUsage};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Extract the model and token usage from a recorded response body
+pub fn extract_usage(body: &BodyData) -> Option<(Option<String>, MessageUsage)> {
+    match &body.content {
+        BodyContent::EventStream { message, .. } => message
+            .usage
+            .clone()
+            .map(|usage| (message.model.clone(), usage)),
+        BodyContent::Text { data } | BodyContent::TruncatedJson { data, .. } => {
+            let json: Value = serde_json::from_str(data).ok()?;
+            let usage = json.get("usage")?.as_object()?;
+            // Drop nulls so absent cache counters default to zero
+            let usage: serde_json::Map<String, Value> = usage
+                .iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            let usage = serde_json::from_value(Value::Object(usage)).ok()?;
+            let model = json.get("model").and_then(Value::as_str).map(String::from);
+            Some((model, usage))
+        }
+        _ => None,
+    }
+}
+
+/// Accumulated usage over a set of API calls
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageTotals {
+    /// Number of responses that reported usage
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_creation_input_tokens: u64,
+    pub cache_read_input_tokens: u64,
+    /// Total cost in USD of the priced requests
+    pub cost_usd: f64,
+    /// Requests whose model had no configured price
+    pub unpriced_requests: u64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, tokens: &MessageUsage, cost_usd: Option<f64>) {
+        self.requests += 1;
+        self.input_tokens += tokens.input_tokens;
+        self.output_tokens += tokens.output_tokens;
+        self.cache_creation_input_tokens += tokens.cache_creation_input_tokens;
+        self.cache_read_input_tokens += tokens.cache_read_input_tokens;
+        match cost_usd {
+            Some(cost) => self.cost_usd += cost,
+            None => self.unpriced_requests += 1,
+        }
+    }
+}
+
+impl fmt::Display for UsageTotals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} request(s), {} input, {} output, {} cache write, {} cache read tokens, ${:.4}",
+            self.requests,
+            self.input_tokens,
+            self.output_tokens,
+            self.cache_creation_input_tokens,
+            self.cache_read_input_tokens,
+            self.cost_usd
+        )?;
+        if self.unpriced_requests > 0 {
+            write!(f, " ({} unpriced)", self.unpriced_requests)?;
+        }
+        Ok(())
+    }
+}
+
+/// Usage totals for one log file
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageSummary {
+    pub total: UsageTotals,
+    pub by_session: BTreeMap<String, UsageTotals>,
+    pub by_model: BTreeMap<String, UsageTotals>,
+}
+
+impl fmt::Display for UsageSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Total: {}", self.total)?;
+
+        if !self.by_model.is_empty() {
+            writeln!(f, "\nBy model:")?;
+            for (model, totals) in &self.by_model {
+                writeln!(f, "  {}: {}", model, totals)?;
+            }
+        }
+
+        if !self.by_session.is_empty() {
+            writeln!(f, "\nBy session:")?;
+            for (session, totals) in &self.by_session {
+                writeln!(f, "  {}: {}", session, totals)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Aggregate the usage recorded on proxy response events
+pub fn summarize<'a>(entries: impl IntoIterator<Item = &'a LogEntry>) -> UsageSummary {
+    let mut summary = UsageSummary::default();
+
+    for entry in entries {
+        let LogEvent::ProxyResponse(resp) = &entry.event else {
+            continue;
+        };
+        let Some(usage) = &resp.usage else {
+            continue;
+        };
+
+        let model = usage.model.clone().unwrap_or_else(|| "unknown".to_string());
+
+        summary.total.add(&usage.tokens, usage.cost_usd);
+        summary
+            .by_session
+            .entry(entry.session_id.clone())
+            .or_default()
+            .add(&usage.tokens, usage.cost_usd);
+        summary
+            .by_model
+            .entry(model)
+            .or_default()
+            .add(&usage.tokens, usage.cost_usd);
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{Headers, ProxyResponseEvent, UsageRecord};
+    use uuid::Uuid;
+
+    fn response_entry(session: &str, model: &str, input: u64, output: u64, cost: Option<f64>) -> LogEntry {
+        LogEntry::new_proxy_response(
+            session.to_string(),
+            session.to_string(),
+            ProxyResponseEvent {
+                usage: Some(UsageRecord {
+                    model: Some(model.to_string()),
+                    tokens: MessageUsage {
+                        input_tokens: input,
+                        output_tokens: output,
+                        ..Default::default()
+                    },
+                    cost_usd: cost,
+                }),
+                ..ProxyResponseEvent::new(
+                    Uuid::new_v4(),
+                    200,
+                    Headers::default(),
+                    BodyData::from_bytes(b"", None, None, 1024),
+                    100,
+                )
+            },
+        )
+    }
+
+    #[test]
+    fn test_extract_usage_from_json_body() {
+        let body = BodyData::from_bytes(
+            br#"{"id":"msg_1","model":"claude-sonnet-4-5","usage":{"input_tokens":10,"output_tokens":5,"cache_creation_input_tokens":null,"cache_read_input_tokens":3}}"#,
+            None,
+            Some("application/json".to_string()),
+            1024,
+        );
+
+        let (model, usage) = extract_usage(&body).unwrap();
+        assert_eq!(model.as_deref(), Some("claude-sonnet-4-5"));
+        assert_eq!(usage.input_tokens, 10);
+        assert_eq!(usage.output_tokens, 5);
+        assert_eq!(usage.cache_creation_input_tokens, 0);
+        assert_eq!(usage.cache_read_input_tokens, 3);
+    }
+
+    #[test]
+    fn test_extract_usage_ignores_other_bodies() {
+        let body = BodyData::from_bytes(b"{\"ok\":true}", None, None, 1024);
+        assert!(extract_usage(&body).is_none());
+
+        let body = BodyData::from_bytes(b"not json", None, None, 1024);
+        assert!(extract_usage(&body).is_none());
+    }
+
+    #[test]
+    fn test_summarize_groups_by_session_and_model() {
+        let entries = vec![
+            response_entry("a", "claude-sonnet-4-5", 100, 10, Some(0.5)),
+            response_entry("a", "claude-haiku-4-5", 50, 5, Some(0.1)),
+            response_entry("b", "mystery-model", 1, 1, None),
+            LogEntry::new_mcp("a".to_string(), "INFO".to_string(), "ignored".to_string()),
+        ];
+
+        let summary = summarize(&entries);
+
+        assert_eq!(summary.total.requests, 3);
+        assert_eq!(summary.total.input_tokens, 151);
+        assert_eq!(summary.total.unpriced_requests, 1);
+        assert!((summary.total.cost_usd - 0.6).abs() < 1e-9);
+
+        assert_eq!(summary.by_session["a"].requests, 2);
+        assert_eq!(summary.by_session["b"].requests, 1);
+        assert_eq!(summary.by_model["claude-haiku-4-5"].output_tokens, 5);
+    }
+}
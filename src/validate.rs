@@ -0,0 +1,233 @@
+//! Checking log files against the published JSON Schema
+//!
+//! `local-logger schema` prints [`crate::schema::json_schema`] for downstream
+//! consumers; this module checks a daily file against that same schema, line
+//! by line, so a consumer's assumptions and the files on disk can be compared.
+
+use crate::schema::{json_schema, SCHEMA_VERSION};
+use serde_json::Value;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// Longest validation message kept per error; messages quote the offending value
+const MAX_MESSAGE_CHARS: usize = 200;
+
+/// A line that does not conform to the schema
+#[derive(Debug, Clone)]
+pub struct InvalidLine {
+    /// 1-based line number in the file
+    pub line: usize,
+    /// One message per violation, prefixed with the JSON pointer it concerns
+    pub errors: Vec<String>,
+}
+
+/// Result of validating one log file
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    /// Non-empty lines checked
+    pub entries: usize,
+    /// Lines that failed, in file order
+    pub invalid: Vec<InvalidLine>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.invalid.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for invalid in &self.invalid {
+            for error in &invalid.errors {
+                writeln!(f, "line {}: {}", invalid.line, error)?;
+            }
+        }
+        writeln!(
+            f,
+            "{} of {} entries conform to schema version {}",
+            self.entries - self.invalid.len(),
+            self.entries,
+            SCHEMA_VERSION
+        )
+    }
+}
+
+/// Validate every line of a log file against the current schema
+pub fn validate_file(path: &Path) -> io::Result<ValidationReport> {
+    let validator =
+        jsonschema::validator_for(&json_schema()).map_err(|e| io::Error::other(e.to_string()))?;
+    let reader = BufReader::new(File::open(path)?);
+    let mut report = ValidationReport::default();
+
+    for (index, line) in reader.split(b'\n').enumerate() {
+        let line = line?;
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        report.entries += 1;
+
+        let errors = match serde_json::from_slice::<Value>(&line) {
+            Ok(instance) => {
+                let mut errors: Vec<String> = validator
+                    .iter_errors(&instance)
+                    .map(|error| {
+                        let path = error.instance_path.to_string();
+                        format!("{}: {}", pointer(&path), shorten(&error.to_string()))
+                    })
+                    .collect();
+                if !errors.is_empty() {
+                    let version = instance["schema_version"].as_u64();
+                    if let Some(version) = version.filter(|v| *v < SCHEMA_VERSION as u64) {
+                        errors.push(format!(
+                            "written with schema version {}, run `local-logger migrate`",
+                            version
+                        ));
+                    }
+                }
+                errors
+            }
+            Err(e) => vec![format!("not JSON: {}", e)],
+        };
+
+        if !errors.is_empty() {
+            report.invalid.push(InvalidLine {
+                line: index + 1,
+                errors,
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+fn pointer(path: &str) -> &str {
+    if path.is_empty() {
+        "/"
+    } else {
+        path
+    }
+}
+
+fn shorten(message: &str) -> String {
+    if message.chars().count() <= MAX_MESSAGE_CHARS {
+        return message.to_string();
+    }
+    let cut: String = message.chars().take(MAX_MESSAGE_CHARS).collect();
+    format!("{}...", cut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_writer::LogWriter;
+    use crate::schema::{
+        BodyData, Headers, LogEntry, LogEvent, ProxyRequestEvent, ProxyResponseEvent, UnknownEvent, UpstreamErrorKind,
+    };
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    /// One entry of each kind, as the writers produce them
+    fn entries() -> Vec<LogEntry> {
+        let request_id = uuid::Uuid::new_v4();
+        let mut newer = LogEntry::new_mcp("s".to_string(), "INFO".to_string(), String::new());
+        newer.event = LogEvent::Unknown(UnknownEvent {
+            kind: "ToolTrace".to_string(),
+            payload: serde_json::json!({"tool": "Bash"}).as_object().unwrap().clone(),
+        });
+        let body = BodyData::from_bytes(
+            br#"{"model":"claude-sonnet-4-5"}"#,
+            None,
+            Some("application/json".to_string()),
+            1024,
+        );
+        vec![
+            LogEntry::new_mcp("s".to_string(), "INFO".to_string(), "hello".to_string()),
+            LogEntry::new_hook(
+                "s".to_string(),
+                "PreToolUse".to_string(),
+                Some("Bash".to_string()),
+                Some(serde_json::json!({"command": "ls"})),
+                None,
+                None,
+                HashMap::new(),
+            ),
+            LogEntry::new_proxy_request(
+                "s".to_string(),
+                "c".to_string(),
+                ProxyRequestEvent {
+                    endpoint_pattern: Some("/v1/messages".to_string()),
+                    ..ProxyRequestEvent::new(
+                        request_id,
+                        "POST".to_string(),
+                        "https://api.anthropic.com/v1/messages".to_string(),
+                        Headers::default(),
+                        body.clone(),
+                    )
+                },
+            ),
+            LogEntry::new_proxy_response(
+                "s".to_string(),
+                "c".to_string(),
+                ProxyResponseEvent {
+                    ttfb_ms: Some(3),
+                    upstream_request_id: Some("req_1".to_string()),
+                    ..ProxyResponseEvent::new(request_id, 200, Headers::default(), body, 12)
+                },
+            ),
+            LogEntry::new_proxy_error(
+                "s".to_string(),
+                "c".to_string(),
+                request_id,
+                UpstreamErrorKind::Timeout,
+                "timed out".to_string(),
+                30_000,
+            ),
+            newer,
+        ]
+    }
+
+    #[test]
+    fn test_written_entries_conform() {
+        let temp_dir = TempDir::new().unwrap();
+        let writer = LogWriter::new(temp_dir.path().to_path_buf()).unwrap();
+        let entries = entries();
+        for entry in &entries {
+            writer.write_sync(entry).unwrap();
+        }
+
+        let report = validate_file(&writer.get_log_file_path(&entries[0].date)).unwrap();
+        assert_eq!(report.entries, entries.len());
+        assert!(report.is_valid(), "{}", report);
+    }
+
+    #[test]
+    fn test_non_conforming_lines_reported() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("2025-01-01.jsonl");
+        let valid = serde_json::to_string(&entries()[0]).unwrap();
+        let mut missing_date = serde_json::to_value(&entries()[0]).unwrap();
+        missing_date.as_object_mut().unwrap().remove("date");
+        let mut old = serde_json::to_value(&entries()[0]).unwrap();
+        old["schema_version"] = 1.into();
+        let mut malformed = serde_json::to_value(&entries()[2]).unwrap();
+        malformed["event"].as_object_mut().unwrap().remove("method");
+        std::fs::write(
+            &path,
+            format!("{}\n\n{}\nnot json\n{}\n{}\n", valid, missing_date, old, malformed),
+        )
+        .unwrap();
+
+        let report = validate_file(&path).unwrap();
+        assert_eq!(report.entries, 5);
+        let lines: Vec<usize> = report.invalid.iter().map(|i| i.line).collect();
+        assert_eq!(lines, vec![3, 4, 5, 6]);
+        assert!(report.invalid[0].errors[0].contains("date"));
+        assert!(report.invalid[1].errors[0].starts_with("not JSON"));
+        assert!(report.invalid[2].errors.iter().any(|e| e.contains("local-logger migrate")));
+        assert!(report.invalid[3].errors[0].starts_with("/event"));
+        assert!(report.to_string().contains("1 of 5 entries conform"));
+    }
+}
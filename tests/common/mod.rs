@@ -5,7 +5,7 @@ use local_logger::schema::LogEntry;
 use serde_json::json;
 use std::fs::{self, File};
 use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use tempfile::TempDir;
 use uuid::Uuid;
@@ -19,7 +19,7 @@ pub fn create_test_log_writer() -> (LogWriter, TempDir) {
 }
 
 /// Create a log file with a specified number of entries
-pub fn create_log_with_entries(dir: &PathBuf, num_entries: usize) -> PathBuf {
+pub fn create_log_with_entries(dir: &Path, num_entries: usize) -> PathBuf {
     let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
     let log_path = dir.join(format!("{}.jsonl", date));
 
@@ -44,7 +44,7 @@ pub fn create_log_with_entries(dir: &PathBuf, num_entries: usize) -> PathBuf {
 
 /// Assert that a LogEntry is valid and well-formed
 pub fn assert_log_entry_valid(entry: &LogEntry) {
-    assert_eq!(entry.schema_version, 1, "Invalid schema version");
+    assert_eq!(entry.schema_version, local_logger::schema::SCHEMA_VERSION, "Invalid schema version");
     assert!(!entry.session_id.is_empty(), "Empty session ID");
     assert!(!entry.correlation_id.is_empty(), "Empty correlation ID");
     assert!(!entry.date.is_empty(), "Empty date");
@@ -152,7 +152,7 @@ pub fn get_binary_path() -> PathBuf {
     if !path.exists() {
         // Try to build it
         Command::new("cargo")
-            .args(&["build", if cfg!(debug_assertions) { "" } else { "--release" }])
+            .args(["build", if cfg!(debug_assertions) { "" } else { "--release" }])
             .output()
             .expect("Failed to build binary");
     }
@@ -162,7 +162,7 @@ pub fn get_binary_path() -> PathBuf {
 
 /// Create a large log file for performance testing
 #[allow(dead_code)]
-pub fn create_large_log_file(dir: &PathBuf, size_mb: usize) -> PathBuf {
+pub fn create_large_log_file(dir: &Path, size_mb: usize) -> PathBuf {
     let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
     let log_path = dir.join(format!("{}.jsonl", date));
 
@@ -280,7 +280,7 @@ mod tests {
     #[test]
     fn test_create_log_with_entries() {
         let temp_dir = TempDir::new().unwrap();
-        let log_path = create_log_with_entries(&temp_dir.path().to_path_buf(), 10);
+        let log_path = create_log_with_entries(temp_dir.path(), 10);
         let entries = verify_jsonl_file(&log_path, 10);
         assert_eq!(entries.len(), 10);
     }
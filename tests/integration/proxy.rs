@@ -1,7 +1,7 @@
 //! Integration tests for proxy mode concurrent writes
 
 use local_logger::log_writer::LogWriter;
-use local_logger::schema::LogEntry;
+use local_logger::schema::{LogEntry, ProxyRequestEvent, ProxyResponseEvent};
 use std::sync::Arc;
 use tempfile::TempDir;
 use tokio::runtime::Runtime;
@@ -33,16 +33,17 @@ fn test_proxy_concurrent_writes_no_corruption() {
                 let request_entry = LogEntry::new_proxy_request(
                     format!("session-{}", i),
                     format!("correlation-{}", i),
-                    uuid::Uuid::new_v4(),
-                    "GET".to_string(),
-                    format!("https://api.example.com/v1/messages/{}", i),
-                    std::collections::HashMap::new(),
-                    request_body,
-                    None,
-                    None,
-                    None,
-                    None,
-                    Some("v1".to_string()),
+                    ProxyRequestEvent {
+                        api_version: Some("v1".to_string()),
+                        http_version: Some("HTTP/1.1".to_string()),
+                        ..ProxyRequestEvent::new(
+                            uuid::Uuid::new_v4(),
+                            "GET".to_string(),
+                            format!("https://api.example.com/v1/messages/{}", i),
+                            local_logger::schema::Headers::default(),
+                            request_body,
+                        )
+                    },
                 );
                 writer.write_async(request_entry).await.unwrap();
 
@@ -56,11 +57,17 @@ fn test_proxy_concurrent_writes_no_corruption() {
                 let response_entry = LogEntry::new_proxy_response(
                     format!("session-{}", i),
                     format!("correlation-{}", i),
-                    uuid::Uuid::new_v4(),
-                    200,
-                    std::collections::HashMap::new(),
-                    response_body,
-                    100,
+                    ProxyResponseEvent {
+                        ttfb_ms: Some(42),
+                        connection_reused: Some(false),
+                        ..ProxyResponseEvent::new(
+                            uuid::Uuid::new_v4(),
+                            200,
+                            local_logger::schema::Headers::default(),
+                            response_body,
+                            100,
+                        )
+                    },
                 );
                 writer.write_async(response_entry).await.unwrap();
             });
@@ -144,16 +151,16 @@ fn test_proxy_high_concurrency_writes() {
                 let entry = LogEntry::new_proxy_request(
                     format!("session-{}", i),
                     format!("correlation-{}", i),
-                    uuid::Uuid::new_v4(),
-                    "POST".to_string(),
-                    "https://api.example.com/v1/messages".to_string(),
-                    std::collections::HashMap::new(),
-                    body,
-                    None,
-                    None,
-                    None,
-                    None,
-                    None,
+                    ProxyRequestEvent {
+                        http_version: Some("HTTP/2.0".to_string()),
+                        ..ProxyRequestEvent::new(
+                            uuid::Uuid::new_v4(),
+                            "POST".to_string(),
+                            "https://api.example.com/v1/messages".to_string(),
+                            local_logger::schema::Headers::default(),
+                            body,
+                        )
+                    },
                 );
                 writer.write_async(entry).await.unwrap();
             });
@@ -254,7 +254,7 @@ proptest! {
         use crate::common::create_log_with_entries;
 
         let temp_dir = TempDir::new().unwrap();
-        let log_path = create_log_with_entries(&temp_dir.path().to_path_buf(), num_entries);
+        let log_path = create_log_with_entries(temp_dir.path(), num_entries);
 
         // Use the tail reading function
         let entries = read_last_n_lines(&log_path, requested).unwrap();